@@ -0,0 +1,114 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use timeracker_common::{ClientError, ClientOptions, TimeRackerClient, DEFAULT_SERVER};
+use crate::output::{CliError, Printer};
+
+// Starting timeracker_core in the background when nothing answers on a local server address,
+// then waiting for it to accept connections. Disabled with `autostart = false` in [conn].
+
+pub const CORE_BINARY: &str = "timeracker_core";
+
+// How long a freshly started core has to accept connections
+const READY_TIMEOUT: Duration = Duration::from_secs(3);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub async fn connect(client_options: ClientOptions, autostart: bool, printer: &Printer) -> Result<TimeRackerClient, CliError> {
+    let server = client_options.server.clone();
+    // A core started here would not be reachable at a remote address
+    if !autostart || !is_local(&server) {
+        return TimeRackerClient::connect(client_options).await.map_err(|e| connection_error(&server, e));
+    }
+
+    // A single attempt, a refused connection being the cue to start the core rather than to retry
+    let mut probe_options = client_options.clone();
+    probe_options.reconnect_attempts = 0;
+    match TimeRackerClient::connect(probe_options).await {
+        Ok(mut client) => {
+            client.options_mut().reconnect_attempts = client_options.reconnect_attempts;
+            return Ok(client);
+        }
+        Err(ClientError::Io(e)) if e.kind() == io::ErrorKind::ConnectionRefused => (),
+        // Possibly a passing failure, the usual retries apply
+        Err(ClientError::Io(_)) | Err(ClientError::Timeout) | Err(ClientError::ConnectionClosed) => {
+            return TimeRackerClient::connect(client_options).await.map_err(|e| connection_error(&server, e));
+        }
+        Err(e) => return Err(connection_error(&server, e)),
+    }
+
+    let mut core = spawn_core(&server).map_err(|e| CliError {
+        kind: "connection".to_string(),
+        code: None,
+        message: format!("Core not running @ {} and {} could not be started: {} (autostart = false in [conn] to disable)",
+                         server, CORE_BINARY, e),
+    })?;
+    printer.note(&format!("N: Started {} (pid {})", CORE_BINARY, core.id()));
+
+    wait_until_ready(&client_options, &mut core).await?;
+    TimeRackerClient::connect(client_options).await.map_err(|e| connection_error(&server, e))
+}
+
+async fn wait_until_ready(client_options: &ClientOptions, core: &mut Child) -> Result<(), CliError> {
+    // Each probe is a single attempt, the retries are done here
+    let mut probe_options = client_options.clone();
+    probe_options.reconnect_attempts = 0;
+
+    let deadline = Instant::now() + READY_TIMEOUT;
+    let mut exit_status = None;
+    while Instant::now() < deadline {
+        tokio::time::sleep(READY_POLL_INTERVAL).await;
+        if let Ok(mut probe) = TimeRackerClient::connect(probe_options.clone()).await {
+            let _ = probe.bye().await;
+            return Ok(());
+        }
+        // The core may exit because another one is starting at the same time, which then answers
+        if exit_status.is_none() {
+            exit_status = core.try_wait().ok().flatten();
+        }
+    }
+
+    let reason = match exit_status {
+        Some(status) => format!("{} exited with {}", CORE_BINARY, status),
+        None => format!("{} not ready after {}s", CORE_BINARY, READY_TIMEOUT.as_secs()),
+    };
+    Err(CliError { kind: "connection".to_string(), code: None, message: format!("Could not start the core @ {}: {}", client_options.server, reason) })
+}
+
+// Starts the core detached from the terminal, listening on `server` unless that is the default address
+fn spawn_core(server: &str) -> io::Result<Child> {
+    let mut command = Command::new(core_binary());
+    if server != DEFAULT_SERVER {
+        command.arg("--listen").arg(server);
+    }
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+
+    // Keeps the core out of the terminal's process group, so that Ctrl-C on the CLI does not stop it
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    command.spawn()
+}
+
+// timeracker_core next to this binary if there is one, otherwise looked up on PATH
+fn core_binary() -> PathBuf {
+    let file_name = format!("{}{}", CORE_BINARY, std::env::consts::EXE_SUFFIX);
+    std::env::current_exe().ok()
+        .map(|exe| exe.with_file_name(&file_name))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(file_name))
+}
+
+fn is_local(server: &str) -> bool {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    matches!(host, "localhost" | "127.0.0.1" | "::1" | "[::1]")
+}
+
+fn connection_error(server: &str, e: ClientError) -> CliError {
+    let mut error = CliError::from(e);
+    error.message = format!("Error while connecting to core @ {} : {}", server, error.message);
+    error
+}
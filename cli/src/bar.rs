@@ -0,0 +1,317 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use chrono::{Local, TimeZone};
+use serde::{Serialize, Deserialize};
+
+use timeracker_common::{format_duration, topic_path, BlockingTimeRackerClient, ClientOptions, DurationStyle, TimeTrackingInterval, TimeTrackingState};
+use crate::output::CliError;
+use crate::status::{self, Template};
+
+// Output for status bars, one run per refresh:
+//   waybar    custom module with "return-type": "json", prints {"text", "alt", "tooltip", "class"}
+//   i3blocks  prints full_text, short_text and color lines, and reads clicks from BLOCK_BUTTON
+//   polybar   custom/script module, prints one line with %{F} colour tags
+// The text is the status template, or OFF; the placeholder is printed when the core does not answer.
+// Clicks are passed back with `bar <bar> --click left|middle|right`, each button being mapped to
+// an action by the [bar] section of the config file, or with the action itself (`--click previous`).
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bar {
+    Waybar,
+    I3blocks,
+    Polybar,
+}
+
+pub const BARS: &[&str] = &["waybar", "i3blocks", "polybar"];
+
+impl FromStr for Bar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "waybar" => Ok(Bar::Waybar),
+            "i3blocks" => Ok(Bar::I3blocks),
+            "polybar" => Ok(Bar::Polybar),
+            other => Err(format!("unknown bar: {} (expected one of {})", other, BARS.join(", "))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClickAction {
+    // OFF when tracking anything, Idle when OFF
+    Toggle,
+    Idle,
+    Off,
+    // Last topic tracked before the current one, Idle and OFF aside
+    Previous,
+    None,
+}
+
+pub const CLICK_ACTIONS: &[&str] = &["toggle", "idle", "off", "previous", "none"];
+
+// Accepted by --click: a mouse button, or an action
+pub const CLICKS: &[&str] = &["left", "middle", "right", "toggle", "idle", "off", "previous"];
+
+impl FromStr for ClickAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "toggle" => Ok(ClickAction::Toggle),
+            "idle" => Ok(ClickAction::Idle),
+            "off" => Ok(ClickAction::Off),
+            "previous" => Ok(ClickAction::Previous),
+            "none" => Ok(ClickAction::None),
+            other => Err(format!("unknown click action: {} (expected one of {})", other, CLICK_ACTIONS.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for ClickAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ClickAction::Toggle => "toggle",
+            ClickAction::Idle => "idle",
+            ClickAction::Off => "off",
+            ClickAction::Previous => "previous",
+            ClickAction::None => "none",
+        };
+        f.write_str(name)
+    }
+}
+
+pub struct BarOptions {
+    pub server: String,
+    pub token: Option<String>,
+    pub duration_style: DurationStyle,
+    pub template: String,
+    pub placeholder: String,
+    // Actions of the left, middle and right buttons
+    pub clicks: [ClickAction; 3],
+}
+
+// What the bar shows, which also picks the colour
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Tracking,
+    Idle,
+    Off,
+    Unreachable,
+}
+
+impl Class {
+    fn name(self) -> &'static str {
+        match self {
+            Class::Tracking => "tracking",
+            Class::Idle => "idle",
+            Class::Off => "off",
+            Class::Unreachable => "unreachable",
+        }
+    }
+
+    fn color(self) -> Option<&'static str> {
+        match self {
+            Class::Tracking => None,
+            Class::Idle => Some("#E5C07B"),
+            Class::Off | Class::Unreachable => Some("#888888"),
+        }
+    }
+}
+
+// Clicks wait longer than refreshes, the user is expecting something to happen
+const CLICK_CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const CLICK_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Runs the action of `click`, if any, then prints the bar output. The output is printed even when
+// the action fails, so that the bar keeps showing the current topic.
+pub fn run(bar: Bar, click: Option<String>, options: &BarOptions) -> Result<(), CliError> {
+    let template = Template::parse(&options.template).map_err(CliError::usage)?;
+    // i3blocks runs the block again on click, with the button in BLOCK_BUTTON
+    let click = click.or_else(|| match bar {
+        Bar::I3blocks => std::env::var("BLOCK_BUTTON").ok().filter(|button| !button.is_empty()),
+        _ => None,
+    });
+    let action = click.as_deref().map(|click| click_action(click, options)).transpose()?;
+    let result = match action {
+        Some(action) if action != ClickAction::None => perform(action, options),
+        _ => Ok(()),
+    };
+
+    let state = status::fetch_state(&options.server, options.token.clone());
+    let now = Local::now().timestamp().max(0) as u64;
+    print!("{}", render(bar, state.as_ref(), &template, now, options));
+    result
+}
+
+// A button (left, middle, right, or 1 to 3 as in BLOCK_BUTTON) or an action name.
+// Other i3blocks buttons, such as the scroll wheel, do nothing.
+fn click_action(click: &str, options: &BarOptions) -> Result<ClickAction, CliError> {
+    match click.trim().to_lowercase().as_str() {
+        "left" | "1" => Ok(options.clicks[0]),
+        "middle" | "2" => Ok(options.clicks[1]),
+        "right" | "3" => Ok(options.clicks[2]),
+        button if button.parse::<u32>().is_ok() => Ok(ClickAction::None),
+        action => action.parse().map_err(|_| {
+            CliError::usage(format!("unknown click: {} (expected left, middle, right, or one of {})", action, CLICK_ACTIONS.join(", ")))
+        }),
+    }
+}
+
+fn perform(action: ClickAction, options: &BarOptions) -> Result<(), CliError> {
+    let mut client_options = ClientOptions::new();
+    client_options.server = options.server.clone();
+    client_options.token = options.token.clone();
+    client_options.connect_timeout = CLICK_CONNECT_TIMEOUT;
+    client_options.request_timeout = CLICK_REQUEST_TIMEOUT;
+    client_options.reconnect_attempts = 0;
+
+    let mut client = BlockingTimeRackerClient::connect(client_options)?;
+    let state = client.get_state()?;
+    let target = match action {
+        ClickAction::Toggle if state.current_topic_id == 0 => 1,
+        ClickAction::Toggle | ClickAction::Off => 0,
+        ClickAction::Idle => 1,
+        ClickAction::Previous => previous_topic(&client.get_intervals()?, &state)
+            .ok_or_else(|| CliError::usage("No previous topic to switch to"))?,
+        ClickAction::None => state.current_topic_id,
+    };
+    if target != state.current_topic_id {
+        client.switch_topic(target)?;
+    }
+    let _ = client.bye();
+    Ok(())
+}
+
+// Most recent topic other than the current one, skipping Idle and deleted topics
+fn previous_topic(intervals: &[TimeTrackingInterval], state: &TimeTrackingState) -> Option<u64> {
+    intervals.iter().rev()
+        .map(|interval| interval.topic_id)
+        .filter(|id| *id != state.current_topic_id && *id > 1)
+        .find(|id| state.topics_tree.iter().any(|topic| topic.id == *id))
+}
+
+fn render(bar: Bar, state: Option<&TimeTrackingState>, template: &Template, now: u64, options: &BarOptions) -> String {
+    let (class, text) = match state {
+        None => (Class::Unreachable, options.placeholder.clone()),
+        Some(state) => match template.render(state, now, options.duration_style) {
+            None => (Class::Off, "OFF".to_string()),
+            Some(text) if state.current_topic_id == 1 => (Class::Idle, text),
+            Some(text) => (Class::Tracking, text),
+        },
+    };
+
+    match bar {
+        Bar::Waybar => {
+            #[derive(Serialize)]
+            struct WaybarRecord<'a> {
+                text: &'a str,
+                // Same as class, for format-icons
+                alt: &'static str,
+                tooltip: String,
+                class: &'static str,
+            }
+
+            let record = WaybarRecord { text: &text, alt: class.name(), tooltip: tooltip(state, now, options), class: class.name() };
+            format!("{}\n", serde_json::to_string(&record).unwrap())
+        }
+        Bar::I3blocks => {
+            let short_text = state
+                .and_then(|state| state.topics_tree.iter().find(|topic| topic.id == state.current_topic_id))
+                .filter(|_| class != Class::Unreachable)
+                .map_or_else(|| text.clone(), |topic| topic.name.clone());
+            // Every line ends with a newline, the color line being left out while tracking
+            let mut lines = vec![text, short_text];
+            lines.extend(class.color().map(str::to_string));
+            lines.iter().map(|line| format!("{}\n", line)).collect()
+        }
+        Bar::Polybar => {
+            // % starts a formatting tag
+            let text = text.replace('%', "%%");
+            match class.color() {
+                Some(color) if !text.is_empty() => format!("%{{F{}}}{}%{{F-}}\n", color, text),
+                _ => format!("{}\n", text),
+            }
+        }
+    }
+}
+
+fn tooltip(state: Option<&TimeTrackingState>, now: u64, options: &BarOptions) -> String {
+    let mut lines = match state {
+        None => vec![format!("Core not running @ {}", options.server)],
+        Some(state) if state.current_topic_id == 0 => vec!["Timetracking disabled".to_string()],
+        Some(state) => {
+            let since = Local.timestamp_opt(state.current_topic_since as i64, 0).single()
+                .map(|since| since.format("%H:%M").to_string())
+                .unwrap_or_default();
+            let duration = state.topics_tree.iter().find(|topic| topic.id == state.current_topic_id)
+                .map_or(0, |topic| topic.duration);
+            vec![
+                topic_path(state, state.current_topic_id),
+                format!("Since {} ({})", since, format_duration(now.saturating_sub(state.current_topic_since), options.duration_style)),
+                format!("Total {}", format_duration(duration, options.duration_style)),
+            ]
+        }
+    };
+
+    let clicks: Vec<String> = ["Left", "Middle", "Right"].iter().zip(options.clicks.iter())
+        .filter(|(_, action)| **action != ClickAction::None)
+        .map(|(button, action)| format!("{} click: {}", button, action))
+        .collect();
+    if !clicks.is_empty() {
+        lines.push(String::new());
+        lines.extend(clicks);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use timeracker_common::{TimeTrackingImplDetails, TimeTrackingTopic};
+
+    fn options() -> BarOptions {
+        BarOptions {
+            server: "127.0.0.1:6000".to_string(),
+            token: None,
+            duration_style: DurationStyle::Short,
+            template: "{topic}".to_string(),
+            placeholder: "down".to_string(),
+            clicks: [ClickAction::Toggle, ClickAction::None, ClickAction::Previous],
+        }
+    }
+
+    fn state(current_topic_id: u64) -> TimeTrackingState {
+        let topics = [(0, "OFF", 0), (1, "Idle", 0), (2, "Work", 0), (3, "ClientA", 2)];
+        TimeTrackingState {
+            last_assigned_topic_id: 3,
+            current_topic_id,
+            current_topic_since: 0,
+            topics_tree: topics.iter().map(|(id, name, parent_id)| TimeTrackingTopic::new(*id, name, *parent_id)).collect(),
+            details: TimeTrackingImplDetails::new()
+        }
+    }
+
+    fn i3blocks(state: Option<&TimeTrackingState>) -> String {
+        render(Bar::I3blocks, state, &Template::parse("{topic}").unwrap(), 60, &options())
+    }
+
+    #[test]
+    fn ends_every_i3blocks_line_with_a_newline_with_or_without_a_color() {
+        assert_eq!(i3blocks(Some(&state(3))), "Work/ClientA\nClientA\n");
+        assert_eq!(i3blocks(Some(&state(1))), "Idle\nIdle\n#E5C07B\n");
+        assert_eq!(i3blocks(Some(&state(0))), "OFF\nOFF\n#888888\n");
+        assert_eq!(i3blocks(None), "down\ndown\n#888888\n");
+    }
+
+    #[test]
+    fn maps_buttons_to_their_actions_and_ignores_other_buttons() {
+        assert_eq!(click_action("left", &options()).ok(), Some(ClickAction::Toggle));
+        assert_eq!(click_action("3", &options()).ok(), Some(ClickAction::Previous));
+        assert_eq!(click_action("4", &options()).ok(), Some(ClickAction::None), "scroll wheel");
+        assert_eq!(click_action("Idle", &options()).ok(), Some(ClickAction::Idle));
+        assert!(click_action("double", &options()).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use clap::{App, AppSettings, ArgSettings};
+
+use timeracker_common::{topic_path, BlockingTimeRackerClient, ClientOptions, DURATION_STYLES, GROUPINGS, PERIODS};
+use crate::bar::{BARS, CLICKS};
+use crate::output::OUTPUT_FORMATS;
+
+// Completion scripts, generated from the clap definition of the command line so that they follow
+// new subcommands and options. Topic arguments (any argument named "topic") are completed with the
+// topics of the core, listed by the hidden `complete-topics` subcommand.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            other => Err(format!("unknown shell: {} (expected one of {})", other, SHELLS.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+        };
+        f.write_str(name)
+    }
+}
+
+// Topic completion runs on every <TAB>, it gives up quickly when the core does not answer.
+// GET_STATE being retried once, this takes at most twice the request timeout.
+const TOPICS_CONNECT_TIMEOUT: Duration = Duration::from_millis(150);
+const TOPICS_REQUEST_TIMEOUT: Duration = Duration::from_millis(200);
+
+// Prints "path<TAB>id N" for every topic but OFF, or nothing if the core cannot be reached
+pub fn print_topics(server: &str, token: Option<String>) {
+    let mut options = ClientOptions::new();
+    options.server = server.to_string();
+    options.token = token;
+    options.connect_timeout = TOPICS_CONNECT_TIMEOUT;
+    options.request_timeout = TOPICS_REQUEST_TIMEOUT;
+    options.reconnect_attempts = 0;
+
+    let state = match BlockingTimeRackerClient::connect(options).and_then(|mut client| client.get_state()) {
+        Ok(state) => state,
+        Err(_) => return,
+    };
+    for topic in state.topics_tree.iter().filter(|topic| topic.id != 0) {
+        println!("{}\tid {}", topic_path(&state, topic.id), topic.id);
+    }
+}
+
+enum Values {
+    None,
+    Any,
+    Files,
+    List(&'static [&'static str]),
+    Topics,
+}
+
+struct Opt {
+    name: String,
+    // Help text of the option, its name if it has none
+    description: String,
+    short: Option<char>,
+    long: Option<String>,
+    values: Values,
+}
+
+struct Command {
+    name: String,
+    description: String,
+    options: Vec<Opt>,
+    // Names and values of the positional arguments, in order
+    positionals: Vec<(String, Values)>,
+    subcommands: Vec<Command>,
+}
+
+// Values accepted by an argument, by argument name
+fn values_of(name: &str) -> Values {
+    match name.replace('-', "_").as_str() {
+        "config" => Values::Files,
+        "format" => Values::List(OUTPUT_FORMATS),
+        "duration_style" => Values::List(DURATION_STYLES),
+        "shell" => Values::List(SHELLS),
+        "bar" => Values::List(BARS),
+        "by" => Values::List(PERIODS),
+        "group" => Values::List(GROUPINGS),
+        "click" => Values::List(CLICKS),
+        "topic" => Values::Topics,
+        _ => Values::Any,
+    }
+}
+
+impl Command {
+    fn from_app(app: &App) -> Command {
+        let mut options = vec![Opt {
+            name: "help".to_string(),
+            description: "Prints help information".to_string(),
+            short: Some('h'),
+            long: Some("help".to_string()),
+            values: Values::None
+        }];
+        let mut positionals = vec![];
+        for arg in app.get_arguments() {
+            let values = match arg.is_set(ArgSettings::TakesValue) {
+                true => values_of(arg.get_name()),
+                false => Values::None,
+            };
+            if arg.get_short().is_none() && arg.get_long().is_none() {
+                positionals.push((arg.get_name().replace('_', " "), values));
+            } else {
+                options.push(Opt {
+                    name: arg.get_name().replace('_', " "),
+                    description: description(arg.get_about(), arg.get_name()),
+                    short: arg.get_short(),
+                    long: arg.get_long().map(str::to_string),
+                    values,
+                });
+            }
+        }
+
+        Command {
+            name: app.get_name().to_string(),
+            description: description(app.get_about(), app.get_name()),
+            options,
+            positionals,
+            subcommands: app.get_subcommands()
+                .filter(|subcommand| !subcommand.is_set(AppSettings::Hidden))
+                .map(Command::from_app)
+                .collect(),
+        }
+    }
+
+    fn flags(&self) -> Vec<String> {
+        let mut flags = vec![];
+        for opt in self.options.iter() {
+            flags.extend(opt.short.map(|short| format!("-{}", short)));
+            flags.extend(opt.long.as_ref().map(|long| format!("--{}", long)));
+        }
+        flags
+    }
+
+    // Flags of the options taking a value, here or in a subcommand, as a bash case pattern such as "-c|--config"
+    fn value_flags_pattern(&self) -> String {
+        let mut patterns: Vec<String> = vec![];
+        for command in std::iter::once(self).chain(self.subcommands.iter()) {
+            for opt in command.options.iter().filter(|opt| !matches!(opt.values, Values::None)) {
+                let pattern = opt.flags_pattern();
+                if !patterns.contains(&pattern) {
+                    patterns.push(pattern);
+                }
+            }
+        }
+        patterns.join("|")
+    }
+}
+
+// On a single line, for the shells to show next to the candidates
+fn description(about: Option<&str>, name: &str) -> String {
+    about.unwrap_or(name).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl Opt {
+    fn flags_pattern(&self) -> String {
+        let mut flags = vec![];
+        flags.extend(self.short.map(|short| format!("-{}", short)));
+        flags.extend(self.long.as_ref().map(|long| format!("--{}", long)));
+        flags.join("|")
+    }
+}
+
+pub fn script(shell: Shell, app: &App) -> String {
+    let command = Command::from_app(app);
+    match shell {
+        Shell::Bash => bash(&command),
+        Shell::Zsh => zsh(&command),
+        Shell::Fish => fish(&command),
+    }
+}
+
+fn bash_values(values: &Values, bin: &str) -> String {
+    match values {
+        Values::None | Values::Any => "COMPREPLY=()".to_string(),
+        Values::Files => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+        Values::List(list) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", list.join(" ")),
+        Values::Topics => format!("COMPREPLY=($(compgen -W \"$({} complete-topics 2>/dev/null | cut -f1)\" -- \"$cur\"))", bin),
+    }
+}
+
+// Completes the values of the options of `command` when `$prev` is one of them
+fn bash_option_values(command: &Command, bin: &str) -> String {
+    let mut cases = String::new();
+    for opt in command.options.iter().filter(|opt| !matches!(opt.values, Values::None)) {
+        cases.push_str(&format!("                {}) {}; return;;\n", opt.flags_pattern(), bash_values(&opt.values, bin)));
+    }
+    if cases.is_empty() {
+        return String::new();
+    }
+    format!("            case \"$prev\" in\n{}            esac\n", cases)
+}
+
+fn bash(command: &Command) -> String {
+    let bin = &command.name;
+    let function = format!("_{}", bin);
+    let mut script = format!("# bash completion for {bin}, generated by `{bin} completions bash`\n# Load with: source <({bin} completions bash)\n\n", bin = bin);
+
+    script.push_str(&format!("{}() {{\n", function));
+    script.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    script.push_str("    local subcommand=\"\" positional=0 i\n");
+    script.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+    script.push_str("        case \"${COMP_WORDS[i]}\" in\n");
+    let value_flags = command.value_flags_pattern();
+    if !value_flags.is_empty() {
+        script.push_str(&format!("            {}) ((i++));;\n", value_flags));
+    }
+    script.push_str("            -*) ;;\n");
+    script.push_str("            *) if [[ -z \"$subcommand\" ]]; then subcommand=\"${COMP_WORDS[i]}\"; else ((positional++)); fi;;\n");
+    script.push_str("        esac\n");
+    script.push_str("    done\n\n");
+
+    script.push_str("    case \"$subcommand\" in\n");
+    script.push_str("        \"\")\n");
+    script.push_str(&bash_option_values(command, bin));
+    let subcommand_names: Vec<&str> = command.subcommands.iter().map(|subcommand| subcommand.name.as_str()).collect();
+    script.push_str(&format!("            COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\"));;\n",
+                             command.flags().join(" "), subcommand_names.join(" ")));
+
+    for subcommand in command.subcommands.iter() {
+        script.push_str(&format!("        {})\n", subcommand.name));
+        script.push_str(&bash_option_values(subcommand, bin));
+        if !subcommand.positionals.is_empty() {
+            script.push_str("            if [[ \"$cur\" != -* ]]; then\n");
+            script.push_str("                case $positional in\n");
+            for (index, (_, values)) in subcommand.positionals.iter().enumerate() {
+                script.push_str(&format!("                    {}) {}; return;;\n", index, bash_values(values, bin)));
+            }
+            script.push_str("                esac\n");
+            script.push_str("            fi\n");
+        }
+        script.push_str(&format!("            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"));;\n", subcommand.flags().join(" ")));
+    }
+    script.push_str("    esac\n");
+    script.push_str("}\n\n");
+    script.push_str(&format!("complete -F {} {}\n", function, bin));
+    script
+}
+
+// Within single quotes, for a _describe entry
+fn zsh_quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+}
+
+// Within single quotes, for the [description] of an _arguments spec
+fn zsh_escape(text: &str) -> String {
+    zsh_quote(text).replace('[', "\\[").replace(']', "\\]")
+}
+
+fn zsh_values(values: &Values, message: &str, bin: &str) -> String {
+    match values {
+        Values::None => String::new(),
+        Values::Any => format!(":{}:", message),
+        Values::Files => format!(":{}:_files", message),
+        Values::List(list) => format!(":{}:({})", message, list.join(" ")),
+        Values::Topics => format!(":{}:_{}_topics", message, bin),
+    }
+}
+
+fn zsh_arguments(command: &Command, bin: &str) -> Vec<String> {
+    let mut specs = vec![];
+    for opt in command.options.iter() {
+        let values = zsh_values(&opt.values, &opt.name, bin);
+        let description = format!("[{}]", zsh_escape(&opt.description));
+        specs.push(match (opt.short, opt.long.as_ref()) {
+            (Some(short), Some(long)) => format!("'(-{short} --{long})'{{-{short},--{long}}}'{description}{values}'",
+                                                 short = short, long = long, description = description, values = values),
+            (Some(short), None) => format!("'-{}{}{}'", short, description, values),
+            (None, Some(long)) => format!("'--{}{}{}'", long, description, values),
+            (None, None) => continue,
+        });
+    }
+    for (index, (name, values)) in command.positionals.iter().enumerate() {
+        specs.push(format!("'{}{}'", index + 1, zsh_values(values, name, bin)));
+    }
+    specs
+}
+
+fn zsh(command: &Command) -> String {
+    let bin = &command.name;
+    let mut script = format!("#compdef {bin}\n# zsh completion for {bin}, generated by `{bin} completions zsh`\n# Install as _{bin} in a directory of $fpath\n\n", bin = bin);
+
+    script.push_str(&format!("_{}_topics() {{\n", bin));
+    script.push_str("    local -a topics\n");
+    script.push_str(&format!("    topics=(${{(f)\"$({} complete-topics 2>/dev/null | sed -e 's/:/\\\\:/g' -e 's/\\t/:/')\"}})\n", bin));
+    script.push_str("    _describe -t topics 'topic' topics\n");
+    script.push_str("}\n\n");
+
+    script.push_str(&format!("_{}() {{\n", bin));
+    script.push_str("    local context state state_descr line\n");
+    script.push_str("    typeset -A opt_args\n");
+    script.push_str("    _arguments -C \\\n");
+    for spec in zsh_arguments(command, bin) {
+        script.push_str(&format!("        {} \\\n", spec));
+    }
+    script.push_str("        '1: :->subcommand' \\\n");
+    script.push_str("        '*:: :->arguments'\n\n");
+    script.push_str("    case $state in\n");
+    script.push_str("        subcommand)\n");
+    script.push_str("            local -a subcommands\n");
+    let subcommand_entries: Vec<String> = command.subcommands.iter()
+        .map(|subcommand| format!("'{}:{}'", subcommand.name, zsh_quote(&subcommand.description)))
+        .collect();
+    script.push_str(&format!("            subcommands=({})\n", subcommand_entries.join(" ")));
+    script.push_str("            _describe -t subcommands 'subcommand' subcommands;;\n");
+    script.push_str("        arguments)\n");
+    script.push_str("            case $line[1] in\n");
+    for subcommand in command.subcommands.iter() {
+        let specs = zsh_arguments(subcommand, bin);
+        script.push_str(&format!("                {}) _arguments {};;\n", subcommand.name, specs.join(" ")));
+    }
+    script.push_str("            esac;;\n");
+    script.push_str("    esac\n");
+    script.push_str("}\n\n");
+    script.push_str(&format!("_{} \"$@\"\n", bin));
+    script
+}
+
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish_values(values: &Values, bin: &str) -> String {
+    match values {
+        Values::None => String::new(),
+        Values::Any => " -r".to_string(),
+        Values::Files => " -r -F".to_string(),
+        Values::List(list) => format!(" -r -a '{}'", list.join(" ")),
+        Values::Topics => format!(" -r -a '({} complete-topics 2>/dev/null)'", bin),
+    }
+}
+
+fn fish_options(command: &Command, condition: &str, bin: &str) -> String {
+    let mut lines = String::new();
+    for opt in command.options.iter() {
+        let mut line = format!("complete -c {} -n '{}'", bin, condition);
+        if let Some(short) = opt.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        if let Some(long) = opt.long.as_ref() {
+            line.push_str(&format!(" -l {}", long));
+        }
+        line.push_str(&fish_values(&opt.values, bin));
+        line.push_str(&format!(" -d '{}'\n", fish_escape(&opt.description)));
+        lines.push_str(&line);
+    }
+    lines
+}
+
+fn fish(command: &Command) -> String {
+    let bin = &command.name;
+    let mut script = format!("# fish completion for {bin}, generated by `{bin} completions fish`\n# Install as ~/.config/fish/completions/{bin}.fish\n\n", bin = bin);
+    script.push_str(&format!("complete -c {} -f\n", bin));
+    script.push_str(&fish_options(command, "__fish_use_subcommand", bin));
+    for subcommand in command.subcommands.iter() {
+        script.push_str(&format!("complete -c {} -n '__fish_use_subcommand' -a {} -d '{}'\n",
+                                 bin, subcommand.name, fish_escape(&subcommand.description)));
+    }
+    for subcommand in command.subcommands.iter() {
+        let condition = format!("__fish_seen_subcommand_from {}", subcommand.name);
+        script.push_str(&fish_options(subcommand, &condition, bin));
+        // Fish cannot tell positional arguments apart, the first one is offered everywhere
+        if let Some((_, values)) = subcommand.positionals.first() {
+            script.push_str(&format!("complete -c {} -n '{}'{}\n", bin, condition, fish_values(values, bin).replacen(" -r", "", 1)));
+        }
+    }
+    script
+}
@@ -0,0 +1,138 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+use timeracker_common::{local_timestamp, start_of_day};
+
+// Times as given on the command line and shown to the user, in the local time zone.
+// The core only deals in seconds since the Unix epoch.
+
+pub const TIME_FORMATS: &str = "2026-10-18, \"2026-10-18 14:05\", 14:05, today, yesterday, or 90m / 2h / 3d ago";
+
+pub fn now() -> u64 {
+    Local::now().timestamp().max(0) as u64
+}
+
+pub fn start_of_today() -> u64 {
+    start_of_day(Local::now().date_naive())
+}
+
+// Monday of the current week
+pub fn start_of_week() -> u64 {
+    let today = Local::now().date_naive();
+    start_of_day(today - Duration::days(today.weekday().num_days_from_monday() as i64))
+}
+
+// One of the TIME_FORMATS
+pub fn parse_time(input: &str) -> Result<u64, String> {
+    parse_time_at(input, now())
+}
+
+// As parse_time, relative to `now` rather than to the current time
+fn parse_time_at(input: &str, now: u64) -> Result<u64, String> {
+    let input = input.trim();
+    let today = local(now).map_or_else(|| Local::now().date_naive(), |now| now.date_naive());
+    match input.to_lowercase().as_str() {
+        "today" => return Ok(start_of_day(today)),
+        "yesterday" => return Ok(start_of_day(today - Duration::days(1))),
+        _ => (),
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return Ok(start_of_day(date));
+    }
+    for format in ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(input, format) {
+            return Ok(local_timestamp(datetime));
+        }
+    }
+    for format in ["%H:%M", "%H:%M:%S"] {
+        if let Ok(time) = NaiveTime::parse_from_str(input, format) {
+            return Ok(local_timestamp(today.and_time(time)));
+        }
+    }
+    if let Some(ago) = parse_ago(input) {
+        return Ok(now.saturating_sub(ago));
+    }
+    Err(format!("invalid time: {} (expected {})", input, TIME_FORMATS))
+}
+
+// "90m", "2h", "3d" as seconds, followed by "ago" or not
+fn parse_ago(input: &str) -> Option<u64> {
+    let input = input.to_lowercase();
+    let input = input.strip_suffix("ago").map_or(input.as_str(), str::trim_end);
+    let unit = match input.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => return None,
+    };
+    let count: u64 = input[..input.len() - 1].parse().ok()?;
+    count.checked_mul(unit)
+}
+
+// Such as "2026-10-18 14:05"
+pub fn format_time(time: u64, format: &str) -> String {
+    local(time).map_or_else(String::new, |time| time.format(format).to_string())
+}
+
+pub fn local(time: u64) -> Option<DateTime<Local>> {
+    Local.timestamp_opt(time as i64, 0).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Once;
+    use chrono::Utc;
+
+    // Central European time, whose DST changes of 2026 are on March 29 and October 25
+    fn central_european_time() {
+        static SET_TZ: Once = Once::new();
+        SET_TZ.call_once(|| std::env::set_var("TZ", "CET-1CEST,M3.5.0,M10.5.0/3"));
+    }
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> u64 {
+        Utc.with_ymd_and_hms(2026, month, day, hour, minute, 0).unwrap().timestamp() as u64
+    }
+
+    #[test]
+    fn parses_dates_and_times_in_local_time() {
+        central_european_time();
+        // Sunday 2026-10-18 20:00 in Paris
+        let now = utc(10, 18, 18, 0);
+
+        assert_eq!(parse_time_at("2026-10-18", now), Ok(utc(10, 17, 22, 0)));
+        assert_eq!(parse_time_at("2026-10-18 14:05", now), Ok(utc(10, 18, 12, 5)));
+        assert_eq!(parse_time_at("2026-10-18T14:05:30", now), Ok(utc(10, 18, 12, 5) + 30));
+        assert_eq!(parse_time_at(" 14:05 ", now), Ok(utc(10, 18, 12, 5)));
+        assert_eq!(parse_time_at("today", now), Ok(utc(10, 17, 22, 0)));
+        assert_eq!(parse_time_at("Yesterday", now), Ok(utc(10, 16, 22, 0)));
+    }
+
+    #[test]
+    fn parses_durations_ago_with_or_without_the_word() {
+        central_european_time();
+        let now = utc(10, 18, 18, 0);
+
+        assert_eq!(parse_time_at("90m", now), Ok(now - 90 * 60));
+        assert_eq!(parse_time_at("2h ago", now), Ok(now - 2 * 3600));
+        assert_eq!(parse_time_at("3D AGO", now), Ok(now - 3 * 86400));
+        assert_eq!(parse_time_at("1w", now), Ok(now - 7 * 86400));
+        assert_eq!(parse_time_at("45sago", now), Ok(now - 45));
+        for invalid in ["ago", "2x ago", "h", "soon", "2026-13-01", "25:00"] {
+            assert!(parse_time_at(invalid, now).unwrap_err().contains(TIME_FORMATS), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn resolves_times_skipped_or_repeated_by_dst_changes() {
+        central_european_time();
+        let now = utc(10, 18, 18, 0);
+
+        // 02:30 does not exist on March 29, the clocks going from 02:00 to 03:00: 03:30 is taken
+        assert_eq!(parse_time_at("2026-03-29 02:30", now), Ok(utc(3, 29, 1, 30)));
+        // 02:30 happens twice on October 25: the first one, still in summer time, is taken
+        assert_eq!(parse_time_at("2026-10-25 02:30", now), Ok(utc(10, 25, 0, 30)));
+    }
+}
@@ -0,0 +1,127 @@
+use serde::Serialize;
+
+use timeracker_common::{ancestors, topic_path, TimeTrackingInterval, TimeTrackingState};
+
+// History of the intervals, newest first, for the log command.
+// The core returns the intervals overlapping the requested period; they are listed whole.
+
+// One interval, as listed by `log`
+#[derive(Serialize)]
+pub struct IntervalRecord {
+    pub topic_id: u64,
+    // Such as "Work/ClientA"
+    pub topic: String,
+    pub start: u64,
+    // None while the interval is running
+    pub end: Option<u64>,
+    // Up to now for the running interval
+    pub duration: u64,
+    pub note: Option<String>,
+}
+
+// One page of the listing
+pub struct Page {
+    pub records: Vec<IntervalRecord>,
+    // 1-based
+    pub page: usize,
+    pub pages: usize,
+    // Intervals on all pages
+    pub total: usize,
+}
+
+// Records of `intervals` spent on `topic_id` or one of its subtopics (all of them when None),
+// newest first, cut into pages of `limit` intervals. Fails when `page` is past the last one.
+pub fn page(state: &TimeTrackingState, intervals: &[TimeTrackingInterval], topic_id: Option<u64>,
+            limit: Option<usize>, page: usize, now: u64) -> Result<Page, String> {
+    let mut records: Vec<IntervalRecord> = intervals.iter().rev()
+        .filter(|interval| topic_id.is_none_or(|topic_id| ancestors(state, interval.topic_id).contains(&topic_id)))
+        .map(|interval| IntervalRecord {
+            topic_id: interval.topic_id,
+            topic: topic_path(state, interval.topic_id),
+            start: interval.start,
+            end: interval.end,
+            duration: interval.end.unwrap_or(now).saturating_sub(interval.start),
+            note: interval.note.clone(),
+        })
+        .collect();
+
+    let total = records.len();
+    let limit = limit.filter(|limit| *limit > 0).unwrap_or(total.max(1));
+    let pages = total.div_ceil(limit).max(1);
+    let page = page.max(1);
+    if page > pages {
+        return Err(format!("Page {} is past the last page, {}", page, pages));
+    }
+    records = records.into_iter().skip((page - 1) * limit).take(limit).collect();
+    Ok(Page { records, page, pages, total })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use timeracker_common::{TimeTrackingImplDetails, TimeTrackingTopic};
+
+    // Work (2), Work/ClientA (3) and Home (4)
+    fn state() -> TimeTrackingState {
+        let topics = [(0, "OFF", 0), (1, "Idle", 0), (2, "Work", 0), (3, "ClientA", 2), (4, "Home", 0)];
+        TimeTrackingState {
+            last_assigned_topic_id: 4,
+            current_topic_id: 4,
+            current_topic_since: 400,
+            topics_tree: topics.iter().map(|(id, name, parent_id)| TimeTrackingTopic::new(*id, name, *parent_id)).collect(),
+            details: TimeTrackingImplDetails::new()
+        }
+    }
+
+    // Oldest first, as returned by the core, the last one still running
+    fn intervals() -> Vec<TimeTrackingInterval> {
+        [(2, 100, Some(200)), (3, 200, Some(250)), (2, 250, Some(300)), (3, 300, Some(400)), (4, 400, None)].iter()
+            .map(|(topic_id, start, end)| TimeTrackingInterval { topic_id: *topic_id, start: *start, end: *end, note: None })
+            .collect()
+    }
+
+    fn starts(page: &Page) -> Vec<u64> {
+        page.records.iter().map(|record| record.start).collect()
+    }
+
+    #[test]
+    fn lists_newest_first_with_the_running_interval_up_to_now() {
+        let page = page(&state(), &intervals(), None, None, 1, 460).unwrap();
+        assert_eq!(starts(&page), vec![400, 300, 250, 200, 100]);
+        assert_eq!((page.page, page.pages, page.total), (1, 1, 5));
+        assert_eq!(page.records[0].duration, 60);
+        assert_eq!(page.records[0].end, None);
+        assert_eq!(page.records[1].topic, "Work/ClientA");
+    }
+
+    #[test]
+    fn filters_on_a_topic_and_its_subtopics() {
+        let work = page(&state(), &intervals(), Some(2), None, 1, 460).unwrap();
+        assert_eq!(starts(&work), vec![300, 250, 200, 100]);
+
+        let client = page(&state(), &intervals(), Some(3), None, 1, 460).unwrap();
+        assert_eq!(starts(&client), vec![300, 200]);
+    }
+
+    #[test]
+    fn cuts_pages_of_the_limit_and_rejects_pages_past_the_last() {
+        let first = page(&state(), &intervals(), None, Some(2), 0, 460).unwrap();
+        assert_eq!(starts(&first), vec![400, 300], "page 0 is taken as the first");
+        assert_eq!((first.page, first.pages, first.total), (1, 3, 5));
+
+        let last = page(&state(), &intervals(), None, Some(2), 3, 460).unwrap();
+        assert_eq!(starts(&last), vec![100]);
+
+        assert_eq!(page(&state(), &intervals(), None, Some(2), 4, 460).err(),
+                   Some("Page 4 is past the last page, 3".to_string()));
+        assert_eq!(page(&state(), &intervals(), None, None, 2, 460).err(),
+                   Some("Page 2 is past the last page, 1".to_string()), "everything is on one page without a limit");
+    }
+
+    #[test]
+    fn gives_a_single_empty_page_without_intervals() {
+        let empty = page(&state(), &[], None, Some(10), 1, 460).unwrap();
+        assert!(empty.records.is_empty());
+        assert_eq!((empty.page, empty.pages, empty.total), (1, 1, 0));
+    }
+}
@@ -1,25 +1,46 @@
-use tokio::net::TcpStream;
-use futures::{SinkExt};
-use tokio_util::codec::{LinesCodec, Framed};
-use tokio::stream::StreamExt;
-use timeracker_common::{ResponseToClient, TimeTrackingState, ClientRequest, TimeTrackingImplDetails};
-use timeracker_common::ResponseToClient::{State, Bye};
-use clap::{Clap, App, AppSettings};
-use directories::ProjectDirs;
+use timeracker_common::{build_report, topic_path, ReportOptions, TimeTrackingState, ClientOptions, DurationStyle, TimeRackerClient, DEFAULT_SERVER};
+use clap::{Clap, App, AppSettings, IntoApp};
 use serde::{Serialize, Deserialize};
-use std::path::{Path};
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+
+mod autostart;
+mod bar;
+mod completions;
+mod local_time;
+mod log;
+mod output;
+mod settings;
+mod status;
+mod topic_path;
+mod tree;
+mod tui;
+use bar::{Bar, BarOptions, ClickAction};
+use output::{ActionRecord, CliError, OutputFormat, Printer};
+use settings::{hidden, shown, Layers, SettingSpec};
+use status::{StatusRecord, Template};
+use topic_path::Resolved;
+use tui::TuiOptions;
 
-extern crate ini;
-use ini::Ini;
-use ini::ini::Error;
 
 #[derive(Clap)]
 #[clap(version = "0.1", author = "liothique <liothique@liothique.xyz>")]
 struct CliOptions {
+    /// Configuration file, instead of the default one
     #[clap(short, long)]
     config: Option<String>,
+    /// Section [profile.<name>] of the config file, overriding the usual sections
+    #[clap(short, long)]
+    profile: Option<String>,
+    /// Address of the core, such as 127.0.0.1:45862
     #[clap(short, long)]
     server: Option<String>,
+    /// How durations are printed: short (1h 23m), clock (01:23:45), decimal (1.39h), days or seconds
+    #[clap(short, long)]
+    duration_style: Option<String>,
+    /// Output format: table (for humans), json, ndjson or csv, see OUTPUT.md
+    #[clap(short, long)]
+    format: Option<String>,
     #[clap(subcommand)]
     subcmd: Option<SubCommand>,
 }
@@ -27,13 +48,30 @@ struct CliOptions {
 #[derive(Serialize, Deserialize)]
 #[derive(Debug)]
 struct Options {
-    server: String
+    server: String,
+    // Sent with AUTH right after connecting, when the core requires it
+    token: Option<String>,
+    autostart: bool,
+    duration_style: DurationStyle,
+    format: OutputFormat,
+    status_template: String,
+    // Printed by status when there is nothing to show
+    status_placeholder: String,
+    // Actions of the left, middle and right buttons in status bars
+    bar_clicks: [ClickAction; 3]
 }
 
 impl Options {
     pub fn new() -> Options {
         Options {
-            server: "localhost:45862".to_string()
+            server: DEFAULT_SERVER.to_string(),
+            token: None,
+            autostart: true,
+            duration_style: DurationStyle::Short,
+            format: OutputFormat::Table,
+            status_template: status::DEFAULT_TEMPLATE.to_string(),
+            status_placeholder: String::new(),
+            bar_clicks: [ClickAction::Toggle, ClickAction::Idle, ClickAction::Previous]
         }
     }
 }
@@ -45,10 +83,33 @@ impl ::std::default::Default for Options {
 #[derive(Clap)]
 #[derive(Debug)]
 enum SubCommand {
+    /// Starts tracking, on Idle, if tracking is OFF
     Enable(Enable),
+    /// Stops tracking
     Disable(Disable),
+    /// Tells the core you are at work, so that it does not fall back to Idle
+    Activity(Activity),
+    /// Switches to a topic, given by path or id
     Switch(Switch),
-    ShowSettings(ShowSettings)
+    /// Shows the topics and the time spent on each (the default)
+    Show(Show),
+    /// Lists the tracked intervals, newest first
+    Log(Log),
+    /// Sums up the time spent per day, week or month
+    Report(ReportCommand),
+    /// Shows the effective settings and where each one comes from
+    ShowSettings(ShowSettings),
+    /// Interactive view of the topics, refreshed live
+    Tui(Tui),
+    /// Prints the current topic on one line, for prompts and status bars
+    Status(Status),
+    /// Prints the current topic for waybar, i3blocks or polybar
+    Bar(BarCommand),
+    /// Prints a completion script for bash, zsh or fish
+    Completions(Completions),
+    /// Used by the completion scripts
+    #[clap(name = "complete-topics", setting = AppSettings::Hidden)]
+    CompleteTopics(CompleteTopics)
 }
 
 
@@ -64,209 +125,378 @@ struct Disable {
 
 #[derive(Clap)]
 #[derive(Debug)]
-struct Switch {
-    id: u64
+struct Activity {
 }
 
 #[derive(Clap)]
 #[derive(Debug)]
-struct ShowSettings {
+struct Switch {
+    /// Topic path such as "Work/ClientA/Review", or id such as #12 (or 12 if no topic is named so); names may be abbreviated
+    topic: String,
+    /// Create the missing topics of the path instead of failing; names are then not matched fuzzily
+    #[clap(long)]
+    create: bool,
+    /// Note attached to the interval starting now, shown by log
+    #[clap(long)]
+    note: Option<String>
 }
 
-
-
-async fn send_bye(lines: &mut Framed<TcpStream, LinesCodec>) {
-    if let Err(e) = lines.send("BYE").await {
-        println!("[E] Error on sending BYE command; error = {:?}", e);
-    }
-
-    if let Some(result) = lines.next().await {
-        match result {
-            Ok(line) => {
-                let response: ResponseToClient = serde_json::from_str(&line).unwrap();
-                match response {
-                    Bye{} => { }
-                    _ => {println!("Unexpect response to BYE command")}
-                }
-            }
-            Err(e) => {
-                println!("[E] Error on decoding from socket; error = {:?}", e);
-            }
-        }
-    } else {
-        println!("[E] Connection error while sending BYE command");
-    }
+#[derive(Clap)]
+#[derive(Debug)]
+struct Show {
+    /// Fold topics nested deeper than this, 0 for top-level topics only
+    #[clap(long)]
+    depth: Option<usize>
 }
 
-// This returns  either the cli options if it was set,
-// or else, the value in the conf file at given section/key if it is found
-// or else, the default value
-fn cli_then_conf_then_default_value(conf_ini: &Ini,
-                                    ini_section: &str,
-                                    ini_key: &str,
-                                    value_cli: Option<String>,
-                                    default_value: String) -> String {
-    value_cli.as_ref().unwrap_or( &conf_ini.get_from(Some(ini_section),
-                                                 ini_key)
-        .unwrap_or(&default_value).to_string()).to_string()
+#[derive(Clap)]
+#[derive(Debug)]
+struct Log {
+    /// Start of the period: 2026-10-18, "2026-10-18 14:05", 14:05, today, yesterday, or 90m / 2h / 3d ago
+    #[clap(long, conflicts_with_all = &["today", "week"])]
+    since: Option<String>,
+    /// End of the period, in the same formats
+    #[clap(long)]
+    until: Option<String>,
+    /// Topic path, or id such as #12; its subtopics are included
+    #[clap(long)]
+    topic: Option<String>,
+    /// Since midnight
+    #[clap(long)]
+    today: bool,
+    /// Since Monday
+    #[clap(long, conflicts_with = "today")]
+    week: bool,
+    /// Intervals per page, newest first
+    #[clap(long)]
+    limit: Option<usize>,
+    /// Page to show, from 1, with --limit
+    #[clap(long, default_value = "1")]
+    page: usize
 }
 
-fn reconcile_from_config_file(config_file_path: &Path, cli_options:&mut CliOptions, options: &mut Options) {
+#[derive(Clap)]
+#[derive(Debug)]
+struct ReportCommand {
+    /// Period of each section: day, week or month
+    #[clap(long, default_value = "week")]
+    by: String,
+    /// Rows of each section: topic, subtree (topics with the time of their subtopics) or tag (#words of the notes)
+    #[clap(long, default_value = "subtree")]
+    group: String,
+    /// Start of the report, in the formats of log --since
+    #[clap(long)]
+    since: Option<String>,
+    /// End of the report, in the same formats
+    #[clap(long)]
+    until: Option<String>,
+    /// Fold subtopics nested deeper than this, 0 for top-level topics only
+    #[clap(long)]
+    depth: Option<usize>
+}
 
+#[derive(Clap)]
+#[derive(Debug)]
+struct ShowSettings {
+}
 
-    let conf_res = Ini::load_from_file(config_file_path);
-    let conf = match conf_res {
-        Ok(c) => { c},
-        Err(e) => {
-            match e {
-                Error::Io(_ee) => { /* If there is no file, no problem just return empty*/ Ini::new()},
-                Error::Parse(ee) => {
-                    /* However, if parse error, signals, then exit*/
-                    println!("Error while reading config file {} : {}", config_file_path.to_str().unwrap(),  ee);
-                    std::process::exit(1);
-                }
-            }
+#[derive(Clap)]
+#[derive(Debug)]
+struct Tui {
+    /// Seconds between two fetches of the state from the core
+    #[clap(long, default_value = "2")]
+    refresh: u64
+}
 
-        }
-    };
+#[derive(Clap)]
+#[derive(Debug)]
+struct Status {
+    /// Such as "{topic} {elapsed:hm}"; placeholders are topic, name, id, elapsed, duration and since
+    #[clap(long)]
+    template: Option<String>,
+    /// Printed instead when tracking is OFF or the core is not running
+    #[clap(long)]
+    placeholder: Option<String>
+}
 
+#[derive(Clap)]
+#[derive(Debug)]
+struct BarCommand {
+    /// waybar, i3blocks or polybar
+    bar: String,
+    /// Button clicked (left, middle or right), or action to run (toggle, idle, off or previous)
+    #[clap(long)]
+    click: Option<String>
+}
 
-    options.server = cli_then_conf_then_default_value (&conf,
-                                                       "conn",
-                                                       "server",
-                                                       cli_options.server.clone(),
-                                                       options.server.clone());
+#[derive(Clap)]
+#[derive(Debug)]
+struct Completions {
+    /// bash, zsh or fish
+    shell: String
+}
 
+#[derive(Clap)]
+#[derive(Debug)]
+struct CompleteTopics {
 }
 
-async fn fetch_remote_state(lines: &mut Framed<TcpStream, LinesCodec>) -> TimeTrackingState {
 
-    // Populate a (fake) remote state before anything is fetched
-    let mut remote_state = TimeTrackingState {
-        last_assigned_topic_id: 1,
-        current_topic_id: 0,
-        topics_tree: vec![],
-        details: TimeTrackingImplDetails::new()
+
+const SERVER: SettingSpec = SettingSpec {
+    name: "server", flag: Some("--server"), variable: "TIMERACKER_SERVER", section: "conn", key: "server"
+};
+// Sent with AUTH right after connecting; not a flag, command lines being visible to other users
+const TOKEN: SettingSpec = SettingSpec {
+    name: "token", flag: None, variable: "TIMERACKER_TOKEN", section: "conn", key: "token"
+};
+// Start timeracker_core when it is not running
+const AUTOSTART: SettingSpec = SettingSpec {
+    name: "autostart", flag: None, variable: "TIMERACKER_AUTOSTART", section: "conn", key: "autostart"
+};
+const DURATION_STYLE: SettingSpec = SettingSpec {
+    name: "duration_style", flag: Some("--duration-style"), variable: "TIMERACKER_DURATION_STYLE", section: "display", key: "duration_style"
+};
+const FORMAT: SettingSpec = SettingSpec {
+    name: "format", flag: Some("--format"), variable: "TIMERACKER_FORMAT", section: "display", key: "format"
+};
+const STATUS_TEMPLATE: SettingSpec = SettingSpec {
+    name: "status_template", flag: Some("status --template"), variable: "TIMERACKER_STATUS_TEMPLATE", section: "status", key: "template"
+};
+const STATUS_PLACEHOLDER: SettingSpec = SettingSpec {
+    name: "status_placeholder", flag: Some("status --placeholder"), variable: "TIMERACKER_STATUS_PLACEHOLDER", section: "status", key: "placeholder"
+};
+const BAR_LEFT_CLICK: SettingSpec = SettingSpec {
+    name: "bar_left_click", flag: None, variable: "TIMERACKER_BAR_LEFT_CLICK", section: "bar", key: "left_click"
+};
+const BAR_MIDDLE_CLICK: SettingSpec = SettingSpec {
+    name: "bar_middle_click", flag: None, variable: "TIMERACKER_BAR_MIDDLE_CLICK", section: "bar", key: "middle_click"
+};
+const BAR_RIGHT_CLICK: SettingSpec = SettingSpec {
+    name: "bar_right_click", flag: None, variable: "TIMERACKER_BAR_RIGHT_CLICK", section: "bar", key: "right_click"
+};
+
+// Reconciles, by order of priority: command line, environment, profile, config file, defaults.
+// The returned layers tell where each value came from.
+fn reconcile_settings(cli_options: &CliOptions, options: &mut Options) -> Result<Layers, String> {
+    let (config_path, config_source) = settings::config_path(cli_options.config.clone());
+    let mut layers = Layers::load(&config_path, config_source, settings::profile(cli_options.profile.clone()))?;
+
+    options.server = layers.resolve(&SERVER, cli_options.server.clone(), Some(options.server.clone()), shown)
+        .unwrap_or_default();
+
+    options.token = layers.resolve(&TOKEN, None, None, hidden);
+
+    let autostart = layers.resolve(&AUTOSTART, None, Some(options.autostart.to_string()), shown).unwrap_or_default();
+    options.autostart = settings::parse_bool(&autostart).map_err(|e| format!("Invalid autostart: {}", e))?;
+
+    let duration_style = layers.resolve(&DURATION_STYLE, cli_options.duration_style.clone(),
+                                        Some(options.duration_style.to_string()), shown).unwrap_or_default();
+    options.duration_style = duration_style.parse().map_err(|e| format!("Invalid duration style: {}", e))?;
+
+    let format = layers.resolve(&FORMAT, cli_options.format.clone(), Some(options.format.to_string()), shown)
+        .unwrap_or_default();
+    options.format = format.parse().map_err(|e| format!("Invalid output format: {}", e))?;
+
+    let (template, placeholder) = match cli_options.subcmd.as_ref() {
+        Some(SubCommand::Status(status)) => (status.template.clone(), status.placeholder.clone()),
+        _ => (None, None),
     };
+    options.status_template = layers.resolve(&STATUS_TEMPLATE, template, Some(options.status_template.clone()), shown)
+        .unwrap_or_default();
+    options.status_placeholder = layers.resolve(&STATUS_PLACEHOLDER, placeholder, Some(String::new()), shown)
+        .unwrap_or_default();
+
+    for (spec, click) in [&BAR_LEFT_CLICK, &BAR_MIDDLE_CLICK, &BAR_RIGHT_CLICK].iter().zip(options.bar_clicks.iter_mut()) {
+        let action = layers.resolve(spec, None, Some(click.to_string()), shown).unwrap_or_default();
+        *click = action.parse().map_err(|e| format!("Invalid {}: {}", spec.name, e))?;
+    }
 
-    lines.send("GET_STATE").await.unwrap();
+    Ok(layers)
+}
 
+// Runs without autostart nor retries, to stay fast when the core is down
+fn status_command(options: &Options, printer: &Printer) -> Result<(), CliError> {
+    let template = Template::parse(&options.status_template).map_err(CliError::usage)?;
+    let state = status::fetch_state(&options.server, options.token.clone());
+    let now = chrono::Local::now().timestamp().max(0) as u64;
+
+    let line = state.as_ref()
+        .and_then(|state| template.render(state, now, options.duration_style))
+        .unwrap_or_else(|| options.status_placeholder.clone());
+    printer.status(&StatusRecord::new(state.as_ref(), now), &line);
+    Ok(())
+}
 
-    if let Some(result) = lines.next().await {
-        match result {
-            Ok(line) => {
-                let response: ResponseToClient = serde_json::from_str(&line).unwrap();
-                match response {
-                    State{value} => {
-                        let state: TimeTrackingState = serde_json::from_str(&value).unwrap();
-                        remote_state = state;
-                    }
-                    _ => {println!("Unexpect other (non-state) format")}
-                }
+// Like status, fast and without autostart, bars running it every few seconds
+fn bar_command(bar_subarg: &BarCommand, options: &Options) -> Result<(), CliError> {
+    let bar: Bar = bar_subarg.bar.parse().map_err(CliError::usage)?;
+    bar::run(bar, bar_subarg.click.clone(), &BarOptions {
+        server: options.server.clone(),
+        token: options.token.clone(),
+        duration_style: options.duration_style,
+        template: options.status_template.clone(),
+        placeholder: options.status_placeholder.clone(),
+        clicks: options.bar_clicks
+    })
+}
 
-            }
-            Err(e) => {
-                println!("[E] Error on decoding from socket; error = {:?}", e);
-            }
-        }
-    } else {
-        println!("[E] Connection error");
+async fn tui_command(tui_subarg: Tui, client: &mut TimeRackerClient, options: &Options) -> Result<(), CliError> {
+    if !std::io::stdout().is_terminal() {
+        return Err(CliError::usage("tui needs a terminal"));
     }
-
-    return remote_state;
+    tui::run(client, TuiOptions {
+        server: options.server.clone(),
+        duration_style: options.duration_style,
+        refresh: Duration::from_secs(tui_subarg.refresh.max(1))
+    }).await
 }
 
-async fn show_state_command(lines: &mut Framed<TcpStream, LinesCodec>) {
-    let remote_state = fetch_remote_state(lines).await;
-
-    let curr_topic_id = remote_state.current_topic_id;
+async fn show_state_command(client: &mut TimeRackerClient, printer: &Printer, depth: Option<usize>) -> Result<(), CliError> {
+    let remote_state = client.get_state().await?;
+    printer.state(&remote_state, depth);
+    Ok(())
+}
 
-    if curr_topic_id == 0 {
-        println!("N: Timetracking disabled (\"enable\" to start tracking)");
+// Lets the user pick among ambiguous matches, when there is a terminal to ask on.
+// Prompts go to stderr, so that stdout only carries the command output.
+fn choose_topic(state: &TimeTrackingState, segment: &str, candidates: &[u64]) -> Option<u64> {
+    eprintln!("\"{}\" matches several topics:", segment);
+    if !std::io::stdin().is_terminal() {
+        for id in candidates {
+            eprintln!("      {:>4}  {}", id, topic_path(state, *id));
+        }
+        return None;
     }
 
-    println!();
-    let curr_level = 0;
-    for topic in remote_state.topics_tree.iter() {
-        if topic.id == 0  {
-            continue;
-        }
-        let is_current = if topic.id == curr_topic_id {"***"} else {"   "};
-        let mut indentation_str = "".to_string();
-        for _n in 0..curr_level {
-            indentation_str += "  ";
-        }
-        println!("  {} {} {:>4}    {:<20}        {:>10} s",is_current, indentation_str, topic.id , topic.name, topic.duration );
+    for (index, id) in candidates.iter().enumerate() {
+        eprintln!("  {:>2}) {}", index + 1, topic_path(state, *id));
     }
+    eprint!("Choice (empty to cancel): ");
+    let _ = std::io::stderr().flush();
 
-    println!();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).ok()?;
+    let index: usize = answer.trim().parse().ok()?;
+    candidates.get(index.checked_sub(1)?).copied()
 }
 
-
-async fn switch_topic_to_id(id: u64, lines: &mut Framed<TcpStream, LinesCodec>) -> bool {
-
-    if let Err(e) = lines.send(ClientRequest::SwitchTopic{id:id}.emit()).await {
-        println!("[E] Error on sending SWITCH_TOPIC command; error = {:?}", e);
-        return false;
+// Creates `segments` one under the other, starting under `parent_id`.
+// Returns the id of the last one, and the paths of those created.
+async fn create_topic_path(parent_id: u64, segments: &[String], client: &mut TimeRackerClient) -> Result<(u64, Vec<String>), CliError> {
+    let mut parent_id = parent_id;
+    let mut created = vec![];
+    for segment in segments {
+        client.create_topic(segment, parent_id).await?;
+        let state = client.get_state().await?;
+        parent_id = state.topics_tree.iter()
+            .find(|topic| topic.parent_id == parent_id && topic.name == *segment)
+            .map(|topic| topic.id)
+            .ok_or_else(|| CliError::usage(format!("Topic {} missing after its creation", segment)))?;
+        created.push(topic_path(&state, parent_id));
     }
+    Ok((parent_id, created))
+}
 
-    if let Some(result) = lines.next().await {
-        match result {
-            Ok(line) => {
-                let response: ResponseToClient = serde_json::from_str(&line).unwrap();
-                match response{
-                    ResponseToClient::Success{details: _} => {  return true; }
-                    ResponseToClient::Error{error_code: _, msg: _} => {  return false; }
-                    _ => {println!("Unexpect response to SWITCH_TOPIC command"); return false;}
-                }
-            }
-            Err(_e) => { panic!("Unexpected error while waiting for SWITCH_TOPIC response");}
+async fn resolve_topic(switch_subarg: &Switch, client: &mut TimeRackerClient) -> Result<(u64, Vec<String>), CliError> {
+    let state = client.get_state().await?;
+    let resolved = topic_path::resolve(&state, &switch_subarg.topic, !switch_subarg.create, &mut |segment, candidates| {
+        choose_topic(&state, segment, candidates)
+    }).map_err(CliError::usage)?;
+
+    match resolved {
+        Resolved::Topic(id) => Ok((id, vec![])),
+        Resolved::Missing { parent_id, segments } if switch_subarg.create => {
+            create_topic_path(parent_id, &segments, client).await
+        }
+        Resolved::Missing { segments, .. } => {
+            Err(CliError::usage(format!("No topic matches \"{}\" (--create to create it)", segments[0])))
         }
-    } else {
-        return false;
     }
+}
 
+async fn log_command(log_subarg: Log, client: &mut TimeRackerClient, printer: &Printer) -> Result<(), CliError> {
+    let since = match (&log_subarg.since, log_subarg.today, log_subarg.week) {
+        (Some(since), _, _) => Some(local_time::parse_time(since).map_err(CliError::usage)?),
+        (None, true, _) => Some(local_time::start_of_today()),
+        (None, false, true) => Some(local_time::start_of_week()),
+        (None, false, false) => None,
+    };
+    let until = log_subarg.until.as_deref().map(local_time::parse_time).transpose().map_err(CliError::usage)?;
+
+    let state = client.get_state().await?;
+    let topic_id = match log_subarg.topic.as_deref() {
+        Some(query) => match topic_path::resolve(&state, query, true, &mut |segment, candidates| choose_topic(&state, segment, candidates))
+            .map_err(CliError::usage)? {
+            Resolved::Topic(id) => Some(id),
+            Resolved::Missing { segments, .. } => return Err(CliError::usage(format!("No topic matches \"{}\"", segments[0]))),
+        },
+        None => None,
+    };
 
+    let intervals = client.get_intervals_between(since, until).await?;
+    let page = log::page(&state, &intervals, topic_id, log_subarg.limit, log_subarg.page, local_time::now())
+        .map_err(CliError::usage)?;
+    printer.intervals(&page);
+    Ok(())
 }
 
-async fn switch_topic_command(switch_subarg: Switch, lines: &mut Framed<TcpStream, LinesCodec>) {
-    let success = switch_topic_to_id(switch_subarg.id, lines).await;
-    if success {
-        println!("R: Topic switched");
-    } else {
-        println!("R: Failed to switch topic");
-    }
-    show_state_command(lines).await;
+async fn report_command(report_subarg: ReportCommand, client: &mut TimeRackerClient, printer: &Printer) -> Result<(), CliError> {
+    let mut report_options = ReportOptions::new(local_time::now());
+    report_options.period = report_subarg.by.parse().map_err(CliError::usage)?;
+    report_options.grouping = report_subarg.group.parse().map_err(CliError::usage)?;
+    report_options.since = report_subarg.since.as_deref().map(local_time::parse_time).transpose().map_err(CliError::usage)?;
+    report_options.until = report_subarg.until.as_deref().map(local_time::parse_time).transpose().map_err(CliError::usage)?;
+
+    let state = client.get_state().await?;
+    let intervals = client.get_intervals_between(report_options.since, report_options.until).await?;
+    printer.report(&build_report(&state, &intervals, &report_options), report_subarg.depth);
+    Ok(())
 }
 
-async fn enable_time_tracking_command(lines: &mut Framed<TcpStream, LinesCodec>) {
-
-    let state = fetch_remote_state(lines).await;
-    if state.current_topic_id != 0 {
-        println!("R: Timetracking already enabled");
-    } else {
-        let success = switch_topic_to_id(1, lines).await;
+async fn switch_topic_command(switch_subarg: Switch, client: &mut TimeRackerClient, printer: &Printer) -> Result<(), CliError> {
+    let (id, created) = resolve_topic(&switch_subarg, client).await?;
+    client.switch_topic(id).await?;
+    if let Some(note) = switch_subarg.note.as_deref() {
+        client.set_note(Some(note)).await?;
+    }
 
-        if !success {
-            println!("[E] Unexpected error while using command SWITCH_TOPIC to enable time tracking.");
-            return;
-        }
+    let state = client.get_state().await?;
+    printer.action(&ActionRecord::new("switch", true, "Topic switched", created, &state), &state);
+    Ok(())
+}
 
-        println!("R: Timetracking enabled");
+async fn enable_time_tracking_command(client: &mut TimeRackerClient, printer: &Printer) -> Result<(), CliError> {
+    let mut state = client.get_state().await?;
+    let changed = state.current_topic_id == 0;
+    if changed {
+        client.switch_topic(1).await?;
+        state = client.get_state().await?;
     }
 
-    show_state_command(lines).await;
+    let message = if changed { "Timetracking enabled" } else { "Timetracking already enabled" };
+    printer.action(&ActionRecord::new("enable", changed, message, vec![], &state), &state);
+    Ok(())
 }
 
-async fn disable_time_tracking_command(lines: &mut Framed<TcpStream, LinesCodec>) {
-    let success = switch_topic_to_id(0, lines).await;
-
-    if !success {
-        println!("[E] Unexpected error while using command SWITCH_TOPIC to disable time tracking.");
-        return;
+async fn disable_time_tracking_command(client: &mut TimeRackerClient, printer: &Printer) -> Result<(), CliError> {
+    let mut state = client.get_state().await?;
+    let changed = state.current_topic_id != 0;
+    if changed {
+        client.switch_topic(0).await?;
+        state = client.get_state().await?;
     }
 
-    show_state_command(lines).await;
+    let message = if changed { "Timetracking disabled" } else { "Timetracking already disabled" };
+    printer.action(&ActionRecord::new("disable", changed, message, vec![], &state), &state);
+    Ok(())
+}
+
+async fn activity_command(client: &mut TimeRackerClient, printer: &Printer) -> Result<(), CliError> {
+    client.report_activity().await?;
+    let state = client.get_state().await?;
+    printer.action(&ActionRecord::new("activity", false, "Activity reported", vec![], &state), &state);
+    Ok(())
 }
 
 
@@ -277,59 +507,101 @@ async fn main() {
         .setting(AppSettings::DisableHelpSubcommand);
 
     // Options gathered from command line
-    let mut cli_opts: CliOptions = CliOptions::parse();
+    let cli_opts: CliOptions = CliOptions::parse();
     // Actual options.
     let mut options: Options = Options::new();
 
-    /* Reconciling between, by order of prority: command line opts, config file options, defaults */
-
-    let mut default_config_path = ProjectDirs::from("com",
-                                      "liothique.xyz",
-                                      "timeracker_cli")
-        .expect("Cannot generate configuration storage directory path").config_dir().to_path_buf();
+    let layers = match reconcile_settings(&cli_opts, &mut options) {
+        Ok(layers) => layers,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
+    let printer = Printer {
+        format: options.format,
+        duration_style: options.duration_style,
+        highlight: std::io::stdout().is_terminal()
+    };
 
-    // For config file path, consider cmd line options then default
-    if cli_opts.config.is_some() {
-        default_config_path = Path::new(&cli_opts.config.clone().unwrap()).to_path_buf()
+    // Commands that work without the core, or must not wait for it
+    match cli_opts.subcmd {
+        Some(SubCommand::Completions(ref subargs)) => {
+            match subargs.shell.parse() {
+                Ok(shell) => print!("{}", completions::script(shell, &CliOptions::into_app())),
+                Err(e) => {
+                    printer.error(&CliError::usage(e));
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(SubCommand::CompleteTopics(_)) => {
+            completions::print_topics(&options.server, options.token.clone());
+            return;
+        }
+        Some(SubCommand::Status(_)) => {
+            if let Err(e) = status_command(&options, &printer) {
+                printer.error(&e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(SubCommand::Bar(ref subargs)) => {
+            if let Err(e) = bar_command(subargs, &options) {
+                printer.error(&e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(SubCommand::ShowSettings(_)) => {
+            printer.settings(layers.records());
+            return;
+        }
+        _ => (),
     }
 
-    reconcile_from_config_file(&default_config_path, &mut cli_opts, &mut options);
-
-
-    /* First establish connection to timeracker_core (required for all actions)  */
-
-    options.server = "localhost:45862".to_string();
+    printer.note(&format!("\nS: {} ", &options.server));
 
-    println!("\nS: {} ", &options.server);
 
+    let mut client_options = ClientOptions::new();
+    client_options.server = options.server.clone();
+    client_options.token = options.token.clone();
 
-    let stream_res = TcpStream::connect(&options.server).await;
-
-    let stream = match stream_res {
-        Ok(s) => {s},
+    let mut client = match autostart::connect(client_options, options.autostart, &printer).await {
+        Ok(c) => {c},
         Err(e) => {
-            println!("Error while connecting to core @ {} : {}", &options.server,  e);
+            printer.error(&e);
             std::process::exit(1);
         }
     };
 
-    let mut lines = Framed::new(stream, LinesCodec::new());
-
-    match cli_opts.subcmd {
+    let result = match cli_opts.subcmd {
         Some(subcmd) => {
             match subcmd {
-                SubCommand::Enable(_subargs) => { enable_time_tracking_command(&mut lines).await},
-                SubCommand::Switch(subargs) => {switch_topic_command(subargs, &mut lines).await},
-                SubCommand::Disable(_subargs) => { disable_time_tracking_command(&mut lines).await},
-                other => { println!("Unexpected subcommand: {:?}", other); }
+                SubCommand::Enable(_subargs) => { enable_time_tracking_command(&mut client, &printer).await},
+                SubCommand::Switch(subargs) => {switch_topic_command(subargs, &mut client, &printer).await},
+                SubCommand::Disable(_subargs) => { disable_time_tracking_command(&mut client, &printer).await},
+                SubCommand::Activity(_subargs) => { activity_command(&mut client, &printer).await},
+                SubCommand::Show(subargs) => { show_state_command(&mut client, &printer, subargs.depth).await},
+                SubCommand::Log(subargs) => { log_command(subargs, &mut client, &printer).await},
+                SubCommand::Report(subargs) => { report_command(subargs, &mut client, &printer).await},
+                SubCommand::Tui(subargs) => { tui_command(subargs, &mut client, &options).await},
+                other => { Err(CliError::usage(format!("Unexpected subcommand: {:?}", other))) }
             }
         },
-        None => {show_state_command(&mut lines).await; }
-    }
+        None => {show_state_command(&mut client, &printer, None).await }
+    };
 
 
     // Cleanly ending communication by sending BYE
-    send_bye(&mut lines).await;
+    if let Err(e) = client.bye().await {
+        printer.error(&CliError::from(e));
+    }
 
+    if let Err(e) = result {
+        printer.error(&e);
+        std::process::exit(1);
+    }
 }
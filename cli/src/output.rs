@@ -0,0 +1,515 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+use unicode_width::UnicodeWidthStr;
+
+use timeracker_common::{ancestors, format_duration, topic_path, ClientError, DurationStyle, Grouping, Report, TimeTrackingState};
+use crate::local_time::format_time;
+use crate::log::{IntervalRecord, Page};
+use crate::settings::SettingRecord;
+use crate::status::StatusRecord;
+use crate::tree::{render_tree, TreeOptions};
+
+// Everything the commands print goes through a Printer, in the format picked with --format.
+// The machine-readable formats (json, ndjson, csv) follow the schemas documented in OUTPUT.md;
+// fields are only ever added to them. Durations are in seconds, times in seconds since the Unix epoch.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    // Text for humans, with the topic tree
+    #[default]
+    Table,
+    // One pretty-printed JSON document
+    Json,
+    // One compact JSON object per line, one line per record
+    Ndjson,
+    // Header line, then one line per record
+    Csv,
+}
+
+pub const OUTPUT_FORMATS: &[&str] = &["table", "json", "ndjson", "csv"];
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            other => Err(format!("unknown output format: {} (expected one of {})", other, OUTPUT_FORMATS.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Table => "table",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+        };
+        f.write_str(name)
+    }
+}
+
+// Error reported by a command. Printed on stderr, as {"error": {...}} in the machine-readable formats.
+#[derive(Serialize, Debug)]
+pub struct CliError {
+    // "usage", "connection", or the kind reported by the core ("not_found", "conflict", ...)
+    pub kind: String,
+    // Error code sent by the core, for the errors it reported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<u64>,
+    pub message: String,
+}
+
+impl CliError {
+    pub fn usage(message: impl Into<String>) -> CliError {
+        CliError { kind: "usage".to_string(), code: None, message: message.into() }
+    }
+}
+
+impl From<ClientError> for CliError {
+    fn from(e: ClientError) -> Self {
+        match e {
+            ClientError::Server(server_error) => CliError {
+                kind: serde_json::to_value(server_error.kind).ok()
+                    .and_then(|kind| kind.as_str().map(str::to_string))
+                    .unwrap_or_else(|| "internal".to_string()),
+                code: Some(server_error.error_code),
+                message: server_error.msg,
+            },
+            ClientError::InvalidRequest(e) => CliError::usage(e.to_string()),
+            other => CliError { kind: "connection".to_string(), code: None, message: other.to_string() },
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} (error {})", self.message, code),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+// Records that can be printed as CSV lines
+trait CsvRecord {
+    const HEADER: &'static [&'static str];
+    fn fields(&self) -> Vec<String>;
+}
+
+// One topic, as listed by `show`
+#[derive(Serialize)]
+pub struct TopicRecord {
+    pub id: u64,
+    pub parent_id: u64,
+    pub name: String,
+    // Such as "Work/ClientA"
+    pub path: String,
+    // 0 for top-level topics
+    pub depth: usize,
+    // Time spent on the topic itself
+    pub duration: u64,
+    // Time spent on the topic and all its descendants
+    pub total: u64,
+    pub current: bool,
+}
+
+impl CsvRecord for TopicRecord {
+    const HEADER: &'static [&'static str] = &["id", "parent_id", "name", "path", "depth", "duration", "total", "current"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.id.to_string(), self.parent_id.to_string(), self.name.clone(), self.path.clone(),
+             self.depth.to_string(), self.duration.to_string(), self.total.to_string(), self.current.to_string()]
+    }
+}
+
+// Output of `show` in the json format; ndjson and csv only list the topics
+#[derive(Serialize)]
+struct StateRecord {
+    // 0 when tracking is OFF
+    current_topic_id: u64,
+    current_topic: String,
+    current_topic_since: u64,
+    topics: Vec<TopicRecord>,
+}
+
+// Outcome of a command changing the state (switch, enable, disable)
+#[derive(Serialize)]
+pub struct ActionRecord {
+    pub command: &'static str,
+    // False when the state already was as requested
+    pub changed: bool,
+    pub message: String,
+    // Paths of the topics created on the way, by `switch --create`
+    pub created: Vec<String>,
+    // After the command
+    pub current_topic_id: u64,
+    pub current_topic: String,
+}
+
+impl ActionRecord {
+    pub fn new(command: &'static str, changed: bool, message: &str, created: Vec<String>, state: &TimeTrackingState) -> ActionRecord {
+        ActionRecord {
+            command,
+            changed,
+            message: message.to_string(),
+            created,
+            current_topic_id: state.current_topic_id,
+            current_topic: topic_path(state, state.current_topic_id),
+        }
+    }
+}
+
+impl CsvRecord for ActionRecord {
+    const HEADER: &'static [&'static str] = &["command", "changed", "message", "created", "current_topic_id", "current_topic"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.command.to_string(), self.changed.to_string(), self.message.clone(), self.created.join(";"),
+             self.current_topic_id.to_string(), self.current_topic.clone()]
+    }
+}
+
+impl CsvRecord for SettingRecord {
+    const HEADER: &'static [&'static str] = &["name", "value", "source", "origin"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.name.to_string(), self.value.clone(), self.source.to_string(), self.origin.clone()]
+    }
+}
+
+impl CsvRecord for StatusRecord {
+    const HEADER: &'static [&'static str] = &["reachable", "tracking", "topic_id", "topic", "since", "elapsed", "duration"];
+
+    fn fields(&self) -> Vec<String> {
+        let optional = |value: Option<u64>| value.map_or_else(String::new, |value| value.to_string());
+        vec![self.reachable.to_string(), self.tracking.to_string(), optional(self.topic_id),
+             self.topic.clone().unwrap_or_default(), optional(self.since), optional(self.elapsed), optional(self.duration)]
+    }
+}
+
+impl CsvRecord for IntervalRecord {
+    const HEADER: &'static [&'static str] = &["topic_id", "topic", "start", "end", "duration", "note"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.topic_id.to_string(), self.topic.clone(), self.start.to_string(),
+             self.end.map_or_else(String::new, |end| end.to_string()), self.duration.to_string(),
+             self.note.clone().unwrap_or_default()]
+    }
+}
+
+// One row of a report period, as printed by ndjson and csv
+#[derive(Serialize)]
+struct ReportRecord<'a> {
+    period: &'a str,
+    period_start: u64,
+    period_end: u64,
+    key: &'a str,
+    topic_id: Option<u64>,
+    depth: usize,
+    duration: u64,
+    total: u64,
+    percent: f64,
+}
+
+impl CsvRecord for ReportRecord<'_> {
+    const HEADER: &'static [&'static str] = &["period", "period_start", "period_end", "key", "topic_id", "depth", "duration", "total", "percent"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.period.to_string(), self.period_start.to_string(), self.period_end.to_string(), self.key.to_string(),
+             self.topic_id.map_or_else(String::new, |id| id.to_string()), self.depth.to_string(),
+             self.duration.to_string(), self.total.to_string(), format!("{:.2}", self.percent)]
+    }
+}
+
+pub struct Printer {
+    pub format: OutputFormat,
+    pub duration_style: DurationStyle,
+    // Bold current topic in the tree
+    pub highlight: bool,
+}
+
+impl Printer {
+    pub fn is_table(&self) -> bool {
+        self.format == OutputFormat::Table
+    }
+
+    // Informational line, for humans only
+    pub fn note(&self, line: &str) {
+        if self.is_table() {
+            println!("{}", line);
+        }
+    }
+
+    pub fn state(&self, state: &TimeTrackingState, depth: Option<usize>) {
+        let topics = topic_records(state);
+        match self.format {
+            OutputFormat::Table => self.tree(state, depth),
+            OutputFormat::Json => print_json(&StateRecord {
+                current_topic_id: state.current_topic_id,
+                current_topic: topic_path(state, state.current_topic_id),
+                current_topic_since: state.current_topic_since,
+                topics
+            }),
+            OutputFormat::Ndjson => topics.iter().for_each(print_json_line),
+            OutputFormat::Csv => print_csv(&topics),
+        }
+    }
+
+    pub fn action(&self, action: &ActionRecord, state: &TimeTrackingState) {
+        match self.format {
+            OutputFormat::Table => {
+                for path in action.created.iter() {
+                    println!("R: Created topic {}", path);
+                }
+                println!("R: {}", action.message);
+                self.tree(state, None);
+            }
+            OutputFormat::Json => print_json(action),
+            OutputFormat::Ndjson => print_json_line(action),
+            OutputFormat::Csv => print_csv(std::slice::from_ref(action)),
+        }
+    }
+
+    pub fn settings(&self, settings: &[SettingRecord]) {
+        #[derive(Serialize)]
+        struct SettingsRecord<'a> {
+            settings: &'a [SettingRecord],
+        }
+
+        match self.format {
+            OutputFormat::Table => {
+                let name_width = settings.iter().map(|setting| setting.name.len()).max().unwrap_or(0);
+                let value_width = settings.iter().map(|setting| setting.value.width()).max().unwrap_or(0);
+                for setting in settings {
+                    println!("  {:<name_width$}  {}{}  ({})", setting.name, setting.value,
+                             " ".repeat(value_width - setting.value.width()), setting.origin, name_width = name_width);
+                }
+            }
+            OutputFormat::Json => print_json(&SettingsRecord { settings }),
+            OutputFormat::Ndjson => settings.iter().for_each(print_json_line),
+            OutputFormat::Csv => print_csv(settings),
+        }
+    }
+
+    // `line` is the rendered template, printed as is by the table format
+    pub fn status(&self, status: &StatusRecord, line: &str) {
+        match self.format {
+            OutputFormat::Table if line.is_empty() => (),
+            OutputFormat::Table => println!("{}", line),
+            OutputFormat::Json => print_json(status),
+            OutputFormat::Ndjson => print_json_line(status),
+            OutputFormat::Csv => print_csv(std::slice::from_ref(status)),
+        }
+    }
+
+    pub fn intervals(&self, page: &Page) {
+        #[derive(Serialize)]
+        struct IntervalsRecord<'a> {
+            intervals: &'a [IntervalRecord],
+            total: usize,
+            page: usize,
+            pages: usize,
+        }
+
+        match self.format {
+            OutputFormat::Table => self.interval_table(page),
+            OutputFormat::Json => print_json(&IntervalsRecord {
+                intervals: &page.records,
+                total: page.total,
+                page: page.page,
+                pages: page.pages
+            }),
+            OutputFormat::Ndjson => page.records.iter().for_each(print_json_line),
+            OutputFormat::Csv => print_csv(&page.records),
+        }
+    }
+
+    // `depth` folds the subtree rows nested deeper, in every format
+    pub fn report(&self, report: &Report, depth: Option<usize>) {
+        let shown = |row_depth: usize| depth.is_none_or(|depth| row_depth <= depth);
+        let records: Vec<ReportRecord> = report.periods.iter()
+            .flat_map(|period| period.rows.iter().filter(|row| shown(row.depth)).map(move |row| ReportRecord {
+                period: &period.label,
+                period_start: period.start,
+                period_end: period.end,
+                key: &row.key,
+                topic_id: row.topic_id,
+                depth: row.depth,
+                duration: row.duration,
+                total: row.total,
+                percent: row.percent,
+            }))
+            .collect();
+
+        match self.format {
+            OutputFormat::Table => self.report_table(report, &shown),
+            OutputFormat::Json => {
+                let mut report = report.clone();
+                for period in report.periods.iter_mut() {
+                    period.rows.retain(|row| shown(row.depth));
+                }
+                print_json(&report)
+            }
+            OutputFormat::Ndjson => records.iter().for_each(print_json_line),
+            OutputFormat::Csv => print_csv(&records),
+        }
+    }
+
+    pub fn error(&self, error: &CliError) {
+        #[derive(Serialize)]
+        struct ErrorRecord<'a> {
+            error: &'a CliError,
+        }
+
+        match self.format {
+            OutputFormat::Table => eprintln!("[E] {}", error),
+            _ => eprintln!("{}", serde_json::to_string(&ErrorRecord { error }).unwrap()),
+        }
+    }
+
+    fn interval_table(&self, page: &Page) {
+        if page.records.is_empty() {
+            match page.total {
+                0 => println!("N: No intervals"),
+                _ => println!("N: No intervals on page {} of {}", page.page, page.pages),
+            }
+            return;
+        }
+
+        let rows: Vec<[String; 5]> = page.records.iter().map(|record| {
+            let start_day = format_time(record.start, "%Y-%m-%d");
+            let end = match record.end {
+                None => "now".to_string(),
+                Some(end) if format_time(end, "%Y-%m-%d") == start_day => format_time(end, "%H:%M"),
+                Some(end) => format_time(end, "%Y-%m-%d %H:%M"),
+            };
+            [format_time(record.start, "%Y-%m-%d %H:%M"), end, format_duration(record.duration, self.duration_style),
+             record.topic.clone(), record.note.clone().unwrap_or_default()]
+        }).collect();
+
+        let header = ["Start", "End", "Duration", "Topic", "Note"];
+        let mut widths = header.map(|title| title.width());
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.width());
+            }
+        }
+        let line = |cells: Vec<&str>| {
+            let padded: Vec<String> = cells.iter().zip(widths.iter())
+                .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - cell.width())))
+                .collect();
+            println!("  {}", padded.join("  ").trim_end());
+        };
+
+        println!();
+        line(header.to_vec());
+        for row in rows.iter() {
+            line(row.iter().map(String::as_str).collect());
+        }
+        println!();
+        if page.page < page.pages {
+            println!("N: Page {} of {}, {} intervals (--page {} for older ones)", page.page, page.pages, page.total, page.page + 1);
+        } else if page.pages > 1 {
+            println!("N: Page {} of {}, {} intervals", page.page, page.pages, page.total);
+        }
+    }
+
+    fn report_table(&self, report: &Report, shown: &dyn Fn(usize) -> bool) {
+        if report.periods.is_empty() {
+            println!("N: Nothing tracked in this period");
+            return;
+        }
+
+        // Subtree rows show the name under their parent, the other groupings the whole key
+        let label = |key: &str, depth: usize| match report.grouping {
+            Grouping::Subtree => format!("{}{}", "  ".repeat(depth), key.rsplit('/').next().unwrap_or(key)),
+            _ => key.to_string(),
+        };
+        let rows = report.periods.iter().flat_map(|period| period.rows.iter()).filter(|row| shown(row.depth));
+        let label_width = rows.clone().map(|row| label(&row.key, row.depth).width()).max().unwrap_or(0).max("Subtotal".len());
+        let duration_width = rows.map(|row| format_duration(row.total, self.duration_style).len())
+            .chain(std::iter::once(format_duration(report.total, self.duration_style).len()))
+            .max().unwrap_or(0);
+
+        for period in report.periods.iter() {
+            let last_day = format_time(period.end.saturating_sub(1), "%Y-%m-%d");
+            let first_day = format_time(period.start, "%Y-%m-%d");
+            let range = if first_day == last_day { String::new() } else { format!("  {} to {}", first_day, last_day) };
+            println!();
+            println!("{}{}", period.label, range);
+            for row in period.rows.iter().filter(|row| shown(row.depth)) {
+                let label = label(&row.key, row.depth);
+                println!("  {}{}  {:>duration_width$}  {:5.1}%", label, " ".repeat(label_width - label.width()),
+                         format_duration(row.total, self.duration_style), row.percent, duration_width = duration_width);
+            }
+            println!("  {:<label_width$}  {:>duration_width$}", "Subtotal", format_duration(period.total, self.duration_style),
+                     label_width = label_width, duration_width = duration_width);
+        }
+        println!();
+        println!("  {:<label_width$}  {:>duration_width$}", "Total", format_duration(report.total, self.duration_style),
+                 label_width = label_width, duration_width = duration_width);
+        println!();
+    }
+
+    fn tree(&self, state: &TimeTrackingState, depth: Option<usize>) {
+        if state.current_topic_id == 0 {
+            println!("N: Timetracking disabled (\"enable\" to start tracking)");
+        }
+
+        println!();
+        let tree_options = TreeOptions { depth, highlight: self.highlight, duration_style: self.duration_style };
+        for line in render_tree(state, &tree_options) {
+            println!("{}", line);
+        }
+        println!();
+    }
+}
+
+pub fn topic_records(state: &TimeTrackingState) -> Vec<TopicRecord> {
+    let ancestors: Vec<(u64, u64, Vec<u64>)> = state.topics_tree.iter()
+        .map(|topic| (topic.id, topic.duration, ancestors(state, topic.id)))
+        .collect();
+
+    state.topics_tree.iter().map(|topic| TopicRecord {
+        id: topic.id,
+        parent_id: topic.parent_id,
+        name: topic.name.clone(),
+        path: topic_path(state, topic.id),
+        depth: ancestors.iter().find(|(id, _, _)| *id == topic.id).map_or(0, |(_, _, chain)| chain.len() - 1),
+        duration: topic.duration,
+        total: ancestors.iter().filter(|(_, _, chain)| chain.contains(&topic.id)).map(|(_, duration, _)| duration).sum(),
+        current: topic.id == state.current_topic_id,
+    }).collect()
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+fn print_json_line<T: Serialize>(value: &T) {
+    println!("{}", serde_json::to_string(value).unwrap());
+}
+
+fn print_csv<R: CsvRecord>(records: &[R]) {
+    println!("{}", R::HEADER.join(","));
+    for record in records {
+        let fields: Vec<String> = record.fields().iter().map(|field| csv_field(field)).collect();
+        println!("{}", fields.join(","));
+    }
+}
+
+// Quotes fields containing separators, quotes or line breaks (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
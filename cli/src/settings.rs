@@ -0,0 +1,195 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use ini::Ini;
+use ini::ini::Error;
+
+// Layered configuration of the CLI. Each setting is taken from, by order of priority:
+//   1. the command line (--server ...)
+//   2. the environment (TIMERACKER_SERVER ...)
+//   3. the [profile.<name>] section of the config file, when a profile is selected
+//   4. its usual section of the config file ([conn] server ...)
+//   5. the default
+// Profile sections use the same keys as the usual sections, without the section: [profile.work] server = ...
+
+pub const CONFIG_FILE_NAME: &str = "config.ini";
+
+// Where the effective value of a setting comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    ConfigFile { path: PathBuf, section: String },
+    Environment { variable: &'static str },
+    CommandLine { flag: &'static str },
+}
+
+impl Source {
+    // Short name, for the machine-readable output of show-settings
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::ConfigFile { .. } => "config",
+            Source::Environment { .. } => "env",
+            Source::CommandLine { .. } => "cli",
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::ConfigFile { path, section } => write!(f, "[{}] in {}", section, path.display()),
+            Source::Environment { variable } => write!(f, "environment variable {}", variable),
+            Source::CommandLine { flag } => write!(f, "command line {}", flag),
+        }
+    }
+}
+
+// Where a setting can be given. Settings without a flag can only be set in the environment or the config file.
+pub struct SettingSpec {
+    pub name: &'static str,
+    pub flag: Option<&'static str>,
+    pub variable: &'static str,
+    pub section: &'static str,
+    pub key: &'static str,
+}
+
+// Effective value of a setting, as listed by show-settings
+#[derive(Serialize)]
+pub struct SettingRecord {
+    pub name: &'static str,
+    // Empty when unset
+    pub value: String,
+    // "default", "config", "env" or "cli"
+    pub source: &'static str,
+    // Such as "[conn] in /home/me/.config/timeracker_cli/config.ini"
+    pub origin: String,
+}
+
+pub struct Layers {
+    config_path: PathBuf,
+    config: Ini,
+    profile: Option<String>,
+    // Effective values so far, in the order they were resolved
+    records: Vec<SettingRecord>,
+}
+
+// Config file path, from --config, TIMERACKER_CONFIG, or the default location
+pub fn config_path(cli_value: Option<String>) -> (PathBuf, Source) {
+    if let Some(path) = cli_value {
+        return (PathBuf::from(path), Source::CommandLine { flag: "--config" });
+    }
+    if let Some(path) = env_value("TIMERACKER_CONFIG") {
+        return (PathBuf::from(path), Source::Environment { variable: "TIMERACKER_CONFIG" });
+    }
+    let path = directories::ProjectDirs::from("com", "liothique.xyz", "timeracker_cli")
+        .map(|dirs| dirs.config_dir().join(CONFIG_FILE_NAME))
+        .unwrap_or_else(|| PathBuf::from(CONFIG_FILE_NAME));
+    (path, Source::Default)
+}
+
+impl Layers {
+    // A missing config file is only an error when it was asked for explicitly
+    pub fn load(config_path: &Path, config_source: Source, profile: Option<(String, Source)>) -> Result<Layers, String> {
+        let config = match Ini::load_from_file(config_path) {
+            Ok(config) => config,
+            Err(Error::Io(_)) if config_source == Source::Default => Ini::new(),
+            Err(Error::Io(e)) => return Err(format!("Cannot read config file {}: {}", config_path.display(), e)),
+            Err(Error::Parse(e)) => return Err(format!("Error while reading config file {} : {}", config_path.display(), e)),
+        };
+
+        let mut records = vec![SettingRecord {
+            name: "config",
+            value: config_path.display().to_string(),
+            source: config_source.kind(),
+            origin: config_source.to_string(),
+        }];
+
+        if let Some((name, source)) = profile.as_ref() {
+            if config.section(Some(profile_section(name))).is_none() {
+                return Err(format!("No [{}] section in config file {}", profile_section(name), config_path.display()));
+            }
+            records.push(SettingRecord { name: "profile", value: name.clone(), source: source.kind(), origin: source.to_string() });
+        }
+
+        Ok(Layers {
+            config_path: config_path.to_path_buf(),
+            config,
+            profile: profile.map(|(name, _)| name),
+            records,
+        })
+    }
+
+    // Effective value of `spec`, None when unset everywhere and without default.
+    // `display` is what show-settings prints, so that secrets can be hidden.
+    pub fn resolve(&mut self, spec: &SettingSpec, cli_value: Option<String>, default: Option<String>,
+                   display: fn(&str) -> String) -> Option<String> {
+        let (value, source) = match self.lookup(spec, cli_value) {
+            Some((value, source)) => (Some(value), source),
+            None => (default, Source::Default),
+        };
+        self.records.push(SettingRecord {
+            name: spec.name,
+            value: value.as_deref().map(display).unwrap_or_default(),
+            source: source.kind(),
+            origin: source.to_string(),
+        });
+        value
+    }
+
+    fn lookup(&self, spec: &SettingSpec, cli_value: Option<String>) -> Option<(String, Source)> {
+        if let (Some(value), Some(flag)) = (cli_value, spec.flag) {
+            return Some((value, Source::CommandLine { flag }));
+        }
+        if let Some(value) = env_value(spec.variable) {
+            return Some((value, Source::Environment { variable: spec.variable }));
+        }
+        let mut sections = vec![];
+        if let Some(profile) = self.profile.as_ref() {
+            sections.push(profile_section(profile));
+        }
+        sections.push(spec.section.to_string());
+        sections.into_iter().find_map(|section| {
+            let value = self.config.get_from(Some(section.as_str()), spec.key)?.to_string();
+            Some((value, Source::ConfigFile { path: self.config_path.clone(), section }))
+        })
+    }
+
+    pub fn records(&self) -> &[SettingRecord] {
+        &self.records
+    }
+}
+
+// Profile selected with --profile or TIMERACKER_PROFILE, if any
+pub fn profile(cli_value: Option<String>) -> Option<(String, Source)> {
+    match cli_value {
+        Some(name) => Some((name, Source::CommandLine { flag: "--profile" })),
+        None => env_value("TIMERACKER_PROFILE").map(|name| (name, Source::Environment { variable: "TIMERACKER_PROFILE" })),
+    }
+}
+
+fn profile_section(name: &str) -> String {
+    format!("profile.{}", name)
+}
+
+// Unset and empty variables are ignored alike
+fn env_value(variable: &str) -> Option<String> {
+    std::env::var(variable).ok().filter(|value| !value.is_empty())
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        other => Err(format!("expected true or false, got {}", other)),
+    }
+}
+
+pub fn shown(value: &str) -> String {
+    value.to_string()
+}
+
+pub fn hidden(_value: &str) -> String {
+    "********".to_string()
+}
@@ -0,0 +1,233 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, TimeZone};
+use serde::Serialize;
+
+use timeracker_common::{format_duration, topic_path, BlockingTimeRackerClient, ClientOptions, DurationStyle, TimeTrackingState};
+
+// One-line status for shell prompts and status bars, such as "Work/ClientA 1h 05m", from a template:
+//   {topic}     path of the current topic      {name}   its name only      {id}   its id
+//   {elapsed}   time since it was switched to  {duration}  total time spent on it
+//   {since}     when it was switched to, as 14:05
+// Durations take a style after a colon: {elapsed:hm} (1h 05m), {elapsed:hms} (1h 05m 12s), {elapsed:m} (65m),
+// or any duration style ({elapsed:clock}); {since:%H:%M:%S} takes a strftime format. {{ and }} are literal braces.
+// The core is asked once, without reconnecting, and given up on after DEADLINE so that a prompt never waits longer.
+
+pub const DEFAULT_TEMPLATE: &str = "{topic} {elapsed}";
+
+// For connecting, authenticating and getting the state altogether
+const DEADLINE: Duration = Duration::from_millis(40);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Topic,
+    Name,
+    Id,
+    Elapsed,
+    Duration,
+    Since,
+}
+
+enum Piece {
+    Literal(String),
+    Field { field: Field, spec: Option<String> },
+}
+
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+// Status as printed by the machine-readable formats
+#[derive(Serialize)]
+pub struct StatusRecord {
+    // False when the core could not be reached in time
+    pub reachable: bool,
+    // False when tracking is OFF, or the core unreachable
+    pub tracking: bool,
+    pub topic_id: Option<u64>,
+    pub topic: Option<String>,
+    pub since: Option<u64>,
+    // Seconds since `since`
+    pub elapsed: Option<u64>,
+    // Total time spent on the topic
+    pub duration: Option<u64>,
+}
+
+impl StatusRecord {
+    pub fn new(state: Option<&TimeTrackingState>, now: u64) -> StatusRecord {
+        let current = state.and_then(|state| {
+            state.topics_tree.iter().find(|topic| topic.id == state.current_topic_id && topic.id != 0)
+                .map(|topic| (state, topic))
+        });
+        StatusRecord {
+            reachable: state.is_some(),
+            tracking: current.is_some(),
+            topic_id: current.map(|(_, topic)| topic.id),
+            topic: current.map(|(state, topic)| topic_path(state, topic.id)),
+            since: current.map(|(state, _)| state.current_topic_since),
+            elapsed: current.map(|(state, _)| now.saturating_sub(state.current_topic_since)),
+            duration: current.map(|(_, topic)| topic.duration),
+        }
+    }
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => { chars.next(); literal.push('{'); }
+                '}' if chars.peek() == Some(&'}') => { chars.next(); literal.push('}'); }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(format!("unclosed {{{} in template ({{{{ and }}}} for literal braces)", placeholder)),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(parse_placeholder(&placeholder)?);
+                }
+                '}' => return Err("unmatched } in template ({{ and }} for literal braces)".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Template { pieces })
+    }
+
+    // None when tracking is OFF
+    pub fn render(&self, state: &TimeTrackingState, now: u64, duration_style: DurationStyle) -> Option<String> {
+        let topic = state.topics_tree.iter().find(|topic| topic.id == state.current_topic_id && topic.id != 0)?;
+        let mut line = String::new();
+        for piece in self.pieces.iter() {
+            match piece {
+                Piece::Literal(literal) => line.push_str(literal),
+                Piece::Field { field, spec } => line.push_str(&match field {
+                    Field::Topic => topic_path(state, topic.id),
+                    Field::Name => topic.name.clone(),
+                    Field::Id => topic.id.to_string(),
+                    Field::Elapsed => format_span(now.saturating_sub(state.current_topic_since), spec.as_deref(), duration_style),
+                    Field::Duration => format_span(topic.duration, spec.as_deref(), duration_style),
+                    Field::Since => Local.timestamp_opt(state.current_topic_since as i64, 0).single()
+                        .map_or_else(String::new, |since| since.format(spec.as_deref().unwrap_or("%H:%M")).to_string()),
+                }),
+            }
+        }
+        Some(line)
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Piece, String> {
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name.trim(), Some(spec.to_string())),
+        None => (placeholder.trim(), None),
+    };
+    let field = match name {
+        "topic" => Field::Topic,
+        "name" => Field::Name,
+        "id" => Field::Id,
+        "elapsed" => Field::Elapsed,
+        "duration" => Field::Duration,
+        "since" => Field::Since,
+        other => return Err(format!("unknown placeholder {{{}}} in template (expected topic, name, id, elapsed, duration or since)", other)),
+    };
+
+    match (field, spec.as_deref()) {
+        (Field::Elapsed, Some(spec)) | (Field::Duration, Some(spec))
+            if !matches!(spec, "hm" | "hms" | "m") && spec.parse::<DurationStyle>().is_err() => {
+            Err(format!("unknown duration format {} in {{{}}} (expected hm, hms, m or a duration style)", spec, placeholder))
+        }
+        (Field::Since, Some(spec)) if StrftimeItems::new(spec).any(|item| matches!(item, Item::Error)) => {
+            Err(format!("invalid time format {} in {{{}}}", spec, placeholder))
+        }
+        (Field::Topic, Some(_)) | (Field::Name, Some(_)) | (Field::Id, Some(_)) => {
+            Err(format!("{{{}}} takes no format", name))
+        }
+        _ => Ok(Piece::Field { field, spec }),
+    }
+}
+
+fn format_span(secs: u64, spec: Option<&str>, duration_style: DurationStyle) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    match spec {
+        Some("hm") => format!("{}h {:02}m", hours, minutes),
+        Some("hms") => format!("{}h {:02}m {:02}s", hours, minutes, seconds),
+        Some("m") => format!("{}m", secs / 60),
+        Some(style) => format_duration(secs, style.parse().unwrap_or(duration_style)),
+        None => format_duration(secs, duration_style),
+    }
+}
+
+// None when the core does not answer within DEADLINE
+pub fn fetch_state(server: &str, token: Option<String>) -> Option<TimeTrackingState> {
+    let mut options = ClientOptions::new();
+    options.server = server.to_string();
+    options.token = token;
+    options.connect_timeout = DEADLINE;
+    options.request_timeout = DEADLINE;
+    options.reconnect_attempts = 0;
+
+    // The socket timeouts bound each step only; a late answer is left to the thread, which ends with the process
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // Dropping the connection is enough for the core, waiting for the answer to BYE would only cost time
+        let state = BlockingTimeRackerClient::connect(options).and_then(|mut client| client.get_state());
+        let _ = sender.send(state.ok());
+    });
+    receiver.recv_timeout(DEADLINE).ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use timeracker_common::{TimeTrackingImplDetails, TimeTrackingTopic};
+
+    // Tracking Work/ClientA since 1000, with 3900 seconds spent on it
+    fn state() -> TimeTrackingState {
+        let mut client = TimeTrackingTopic::new(3, "ClientA", 2);
+        client.duration = 3900;
+        TimeTrackingState {
+            last_assigned_topic_id: 3,
+            current_topic_id: 3,
+            current_topic_since: 1000,
+            topics_tree: vec![TimeTrackingTopic::new(0, "OFF", 0), TimeTrackingTopic::new(2, "Work", 0), client],
+            details: TimeTrackingImplDetails::new()
+        }
+    }
+
+    fn render(template: &str) -> Option<String> {
+        Template::parse(template).unwrap().render(&state(), 1000 + 3912, DurationStyle::Short)
+    }
+
+    #[test]
+    fn renders_fields_with_their_formats_and_literal_braces() {
+        assert_eq!(render("{topic} {elapsed:hm}").as_deref(), Some("Work/ClientA 1h 05m"));
+        assert_eq!(render("{{{name}}} #{id} {duration:m} {elapsed:hms}").as_deref(), Some("{ClientA} #3 65m 1h 05m 12s"));
+
+        let mut off = state();
+        off.current_topic_id = 0;
+        assert_eq!(Template::parse("{topic}").unwrap().render(&off, 0, DurationStyle::Short), None);
+    }
+
+    #[test]
+    fn rejects_unknown_unclosed_and_unmatched_placeholders() {
+        let error = |template| Template::parse(template).err().unwrap_or_default();
+        assert!(error("{topic").starts_with("unclosed {topic in template"), "{}", error("{topic"));
+        assert!(error("{elapsed:hm").starts_with("unclosed {elapsed:hm"));
+        assert!(error("{title}").starts_with("unknown placeholder {title}"));
+        assert!(error("topic}").starts_with("unmatched }"));
+        assert_eq!(error("{topic:hm}"), "{topic} takes no format");
+        assert!(error("{elapsed:weeks}").starts_with("unknown duration format weeks"));
+    }
+}
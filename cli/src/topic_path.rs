@@ -0,0 +1,195 @@
+use timeracker_common::{TimeTrackingState, TimeTrackingTopic};
+
+// Resolution of topics given by the user as "Work/ClientA/Review" rather than by id.
+// Each segment is matched among the children of the previous one: exact name first,
+// then unique prefix, then fuzzy (letters in order), all case-insensitive.
+// Ids are written "#12" or "id:12", so that a topic may be named "2026". A bare number is
+// also taken as an id, as it used to be, unless a topic has that exact name.
+
+pub enum Resolved {
+    Topic(u64),
+    // `segments` do not exist yet, the first one would go under `parent_id`
+    Missing { parent_id: u64, segments: Vec<String> },
+}
+
+// Called with the segment and the matching topic ids when a choice is needed.
+// Returns None to give up.
+pub type Chooser<'a> = dyn FnMut(&str, &[u64]) -> Option<u64> + 'a;
+
+// Without `fuzzy`, a segment only matches names it equals or starts, so that a topic about
+// to be created is not mistaken for an unrelated one.
+pub fn resolve(state: &TimeTrackingState, query: &str, fuzzy: bool, choose: &mut Chooser) -> Result<Resolved, String> {
+    if let Some(id) = parse_id(query.trim()) {
+        let id = id.map_err(|_| format!("Invalid topic id \"{}\"", query.trim()))?;
+        return match state.topics_tree.iter().any(|topic| topic.id == id) {
+            true => Ok(Resolved::Topic(id)),
+            false => Err(format!("No topic with id {}", id)),
+        };
+    }
+
+    if let Ok(id) = query.trim().parse::<u64>() {
+        let named = state.topics_tree.iter().any(|topic| topic.name.eq_ignore_ascii_case(query.trim()));
+        if !named && state.topics_tree.iter().any(|topic| topic.id == id) {
+            return Ok(Resolved::Topic(id));
+        }
+    }
+
+    let segments: Vec<&str> = query.split('/').map(str::trim).filter(|segment| !segment.is_empty()).collect();
+    if segments.is_empty() {
+        return Err("Empty topic path".to_string());
+    }
+
+    // A lone name may designate a topic anywhere in the tree
+    if segments.len() == 1 && match_segment(segments[0], children(state, 0), fuzzy).is_empty() {
+        let everywhere = match_segment(segments[0], state.topics_tree.iter(), fuzzy);
+        if !everywhere.is_empty() {
+            return pick(segments[0], &everywhere, choose).map(Resolved::Topic);
+        }
+    }
+
+    let mut parent_id = 0;
+    for (index, segment) in segments.iter().enumerate() {
+        let matches = match_segment(segment, children(state, parent_id), fuzzy);
+        if matches.is_empty() {
+            return Ok(Resolved::Missing {
+                parent_id,
+                segments: segments[index..].iter().map(|segment| segment.to_string()).collect()
+            });
+        }
+        parent_id = pick(segment, &matches, choose)?;
+    }
+    Ok(Resolved::Topic(parent_id))
+}
+
+// The number of "#12" or "id:12", None for anything else
+fn parse_id(query: &str) -> Option<Result<u64, std::num::ParseIntError>> {
+    query.strip_prefix('#').or_else(|| query.strip_prefix("id:")).map(|id| id.trim().parse())
+}
+
+fn children(state: &TimeTrackingState, parent_id: u64) -> impl Iterator<Item = &TimeTrackingTopic> {
+    state.topics_tree.iter().filter(move |topic| topic.parent_id == parent_id)
+}
+
+fn pick(segment: &str, matches: &[u64], choose: &mut Chooser) -> Result<u64, String> {
+    if let [id] = matches {
+        return Ok(*id);
+    }
+    choose(segment, matches).ok_or_else(|| format!("\"{}\" is ambiguous", segment))
+}
+
+// Ids of the best matches: exact names if any, else prefixes, else fuzzy matches if allowed
+fn match_segment<'a>(segment: &str, candidates: impl Iterator<Item = &'a TimeTrackingTopic>, fuzzy: bool) -> Vec<u64> {
+    let segment = segment.to_lowercase();
+    let candidates: Vec<(u64, String)> = candidates.map(|topic| (topic.id, topic.name.to_lowercase())).collect();
+
+    let tiers: [&dyn Fn(&str) -> bool; 3] = [
+        &|name| name == segment,
+        &|name| name.starts_with(&segment),
+        &|name| is_subsequence(&segment, name),
+    ];
+    let tiers = if fuzzy { &tiers[..] } else { &tiers[..2] };
+    for matches_tier in tiers.iter() {
+        let matches: Vec<u64> = candidates.iter()
+            .filter(|(_, name)| matches_tier(name))
+            .map(|(id, _)| *id)
+            .collect();
+        if !matches.is_empty() {
+            return matches;
+        }
+    }
+    vec![]
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use timeracker_common::TimeTrackingImplDetails;
+
+    // OFF, Idle, Work (2), Work/ClientA (3), Work/ClientA/Meetings (4), Workshop (5), Home (6),
+    // Home/Meetings (7) and 7 (8)
+    fn state() -> TimeTrackingState {
+        let topics = [(0, "OFF", 0), (1, "Idle", 0), (2, "Work", 0), (3, "ClientA", 2), (4, "Meetings", 3),
+                      (5, "Workshop", 0), (6, "Home", 0), (7, "Meetings", 6), (8, "7", 0)];
+        TimeTrackingState {
+            last_assigned_topic_id: 8,
+            current_topic_id: 0,
+            current_topic_since: 0,
+            topics_tree: topics.iter().map(|(id, name, parent_id)| TimeTrackingTopic::new(*id, name, *parent_id)).collect(),
+            details: TimeTrackingImplDetails::new()
+        }
+    }
+
+    // Resolves to a topic id, failing the test if a choice is needed
+    fn topic(query: &str, fuzzy: bool) -> Result<u64, String> {
+        match resolve(&state(), query, fuzzy, &mut |segment, _| panic!("no choice expected for {}", segment))? {
+            Resolved::Topic(id) => Ok(id),
+            Resolved::Missing { segments, .. } => Err(format!("missing {}", segments.join("/"))),
+        }
+    }
+
+    #[test]
+    fn matches_exact_names_then_prefixes_then_letters_in_order() {
+        assert_eq!(topic("work", true), Ok(2), "exact name rather than the Workshop prefix");
+        assert_eq!(topic("Work/clienta", true), Ok(3));
+        assert_eq!(topic("works", true), Ok(5));
+        assert_eq!(topic("Work/Cl/Meet", true), Ok(4));
+        assert_eq!(topic("hme", true), Ok(6));
+        assert_eq!(topic(" Home / Meetings ", true), Ok(7));
+    }
+
+    #[test]
+    fn skips_fuzzy_matching_when_asked_to() {
+        assert_eq!(topic("hme", false), Err("missing hme".to_string()));
+        assert_eq!(topic("ho", false), Ok(6), "prefixes still match");
+    }
+
+    #[test]
+    fn finds_a_lone_name_anywhere_in_the_tree() {
+        assert_eq!(topic("clienta", true), Ok(3));
+
+        let mut offered = vec![];
+        let result = resolve(&state(), "meetings", true, &mut |_, candidates| {
+            offered = candidates.to_vec();
+            None
+        });
+        assert_eq!(result.err(), Some("\"meetings\" is ambiguous".to_string()));
+        offered.sort_unstable();
+        assert_eq!(offered, vec![4, 7]);
+
+        let chosen = resolve(&state(), "meetings", true, &mut |_, _| Some(7)).unwrap();
+        assert!(matches!(chosen, Resolved::Topic(7)));
+    }
+
+    #[test]
+    fn takes_ids_with_or_without_a_prefix() {
+        assert_eq!(topic("#3", true), Ok(3));
+        assert_eq!(topic("id:4", true), Ok(4));
+        assert_eq!(topic("#42", true), Err("No topic with id 42".to_string()));
+        assert_eq!(topic("#x", true), Err("Invalid topic id \"#x\"".to_string()));
+
+        assert_eq!(topic("6", true), Ok(6), "a bare number is an id when no topic has it as name");
+        assert_eq!(topic("7", true), Ok(8), "unless a topic has it as name");
+        assert_eq!(topic("#8", true), Ok(8));
+    }
+
+    #[test]
+    fn reports_the_missing_segments_to_create() {
+        match resolve(&state(), "Work/ClientA/Review/Notes", false, &mut |_, _| None).unwrap() {
+            Resolved::Missing { parent_id, segments } => {
+                assert_eq!(parent_id, 3);
+                assert_eq!(segments, vec!["Review", "Notes"]);
+            }
+            Resolved::Topic(id) => panic!("resolved to {}", id),
+        }
+        match resolve(&state(), "Garden", false, &mut |_, _| None).unwrap() {
+            Resolved::Missing { parent_id, segments } => assert_eq!((parent_id, segments), (0, vec!["Garden".to_string()])),
+            Resolved::Topic(id) => panic!("resolved to {}", id),
+        }
+        assert_eq!(topic("", true), Err("Empty topic path".to_string()));
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashSet};
+use unicode_width::UnicodeWidthStr;
+
+use timeracker_common::{format_duration, DurationStyle, TimeTrackingState, TimeTrackingTopic};
+
+// Tree view of the topics, as printed by `show`:
+//
+//     *    2  Work            2m 00s   5m 10s
+//     *    3  ├─ ClientA      1m 40s   3m 10s
+//   ***    4  │  └─ Review    1m 30s   1m 30s
+//
+// The current topic is marked with ***, its ancestors with *.
+
+pub struct TreeOptions {
+    // Topics deeper than this are folded into their ancestor, 0 for top-level topics only
+    pub depth: Option<usize>,
+    // Bold current topic and ancestors, for terminals
+    pub highlight: bool,
+    pub duration_style: DurationStyle,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Current,
+    Ancestor,
+    None,
+}
+
+struct Row {
+    id: u64,
+    label: String,
+    own: u64,
+    total: u64,
+    mark: Mark,
+    // Descendants folded because of the depth limit
+    hidden: usize,
+}
+
+struct Tree<'a> {
+    children: BTreeMap<u64, Vec<&'a TimeTrackingTopic>>,
+    roots: Vec<&'a TimeTrackingTopic>,
+    current_path: HashSet<u64>,
+    current_topic_id: u64,
+    max_depth: Option<usize>,
+}
+
+impl<'a> Tree<'a> {
+    fn new(state: &'a TimeTrackingState, max_depth: Option<usize>) -> Tree<'a> {
+        let known: HashSet<u64> = state.topics_tree.iter().map(|topic| topic.id).collect();
+        let mut children: BTreeMap<u64, Vec<&TimeTrackingTopic>> = BTreeMap::new();
+        let mut roots = vec![];
+
+        // OFF is not a topic one spends time on, it is left out
+        for topic in state.topics_tree.iter().filter(|topic| topic.id != 0) {
+            // Topics whose parent vanished are shown at the top rather than lost
+            if topic.parent_id == 0 || !known.contains(&topic.parent_id) || topic.parent_id == topic.id {
+                roots.push(topic);
+            } else {
+                children.entry(topic.parent_id).or_default().push(topic);
+            }
+        }
+
+        let mut current_path = HashSet::new();
+        let mut id = state.current_topic_id;
+        while id != 0 && current_path.insert(id) {
+            id = state.topics_tree.iter().find(|topic| topic.id == id).map_or(0, |topic| topic.parent_id);
+        }
+
+        Tree { children, roots, current_path, current_topic_id: state.current_topic_id, max_depth }
+    }
+
+    fn children_of(&self, id: u64) -> &[&'a TimeTrackingTopic] {
+        self.children.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    // Own duration plus the durations of every descendant, and the number of descendants
+    fn subtree(&self, topic: &TimeTrackingTopic, visited: &mut HashSet<u64>) -> (u64, usize) {
+        let mut total = topic.duration;
+        let mut count = 0;
+        for child in self.children_of(topic.id) {
+            if visited.insert(child.id) {
+                let (child_total, child_count) = self.subtree(child, visited);
+                total += child_total;
+                count += child_count + 1;
+            }
+        }
+        (total, count)
+    }
+
+    fn mark(&self, id: u64) -> Mark {
+        if id == self.current_topic_id {
+            Mark::Current
+        } else if self.current_path.contains(&id) {
+            Mark::Ancestor
+        } else {
+            Mark::None
+        }
+    }
+
+    fn visit(&self, topic: &TimeTrackingTopic, prefix: &str, connector: &str, depth: usize,
+             visited: &mut HashSet<u64>, rows: &mut Vec<Row>) {
+        let (total, descendants) = self.subtree(topic, &mut visited.clone());
+        let folded = self.max_depth.is_some_and(|max_depth| depth >= max_depth);
+
+        rows.push(Row {
+            id: topic.id,
+            label: format!("{}{}{}", prefix, connector, topic.name),
+            own: topic.duration,
+            total,
+            mark: self.mark(topic.id),
+            hidden: if folded { descendants } else { 0 },
+        });
+        if folded {
+            return;
+        }
+
+        let child_prefix = match connector {
+            "├─ " => format!("{}│  ", prefix),
+            "└─ " => format!("{}   ", prefix),
+            _ => prefix.to_string(),
+        };
+        let children = self.children_of(topic.id);
+        for (index, child) in children.iter().enumerate() {
+            if !visited.insert(child.id) {
+                continue;
+            }
+            let connector = if index + 1 == children.len() { "└─ " } else { "├─ " };
+            self.visit(child, &child_prefix, connector, depth + 1, visited, rows);
+        }
+    }
+}
+
+pub fn render_tree(state: &TimeTrackingState, options: &TreeOptions) -> Vec<String> {
+    render_tree_rows(state, options).into_iter().map(|(_, line)| line).collect()
+}
+
+// Lines of the tree, each with the id of the topic it shows
+pub fn render_tree_rows(state: &TimeTrackingState, options: &TreeOptions) -> Vec<(u64, String)> {
+    let tree = Tree::new(state, options.depth);
+    let mut rows = vec![];
+    let mut visited = HashSet::new();
+    for root in tree.roots.iter() {
+        if visited.insert(root.id) {
+            tree.visit(root, "", "", 0, &mut visited, &mut rows);
+        }
+    }
+
+    let label_width = rows.iter().map(|row| display_label(row).width()).max().unwrap_or(0);
+    let format = |secs: u64| format_duration(secs, options.duration_style);
+    let own_width = rows.iter().map(|row| format(row.own).len()).max().unwrap_or(0);
+    let total_width = rows.iter().map(|row| format(row.total).len()).max().unwrap_or(0);
+
+    rows.iter().map(|row| {
+        let label = display_label(row);
+        let padding = " ".repeat(label_width - label.width());
+        let mark = match row.mark {
+            Mark::Current => "***",
+            Mark::Ancestor => "  *",
+            Mark::None => "   ",
+        };
+        let label = if options.highlight && row.mark != Mark::None {
+            format!("\x1b[1m{}\x1b[0m", label)
+        } else {
+            label
+        };
+        (row.id, format!("  {} {:>4}  {}{}  {:>own_width$}  {:>total_width$}", mark, row.id, label, padding,
+                         format(row.own), format(row.total), own_width = own_width, total_width = total_width))
+    }).collect()
+}
+
+fn display_label(row: &Row) -> String {
+    match row.hidden {
+        0 => row.label.clone(),
+        hidden => format!("{} (+{})", row.label, hidden),
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::io::{Stdout, Write};
+use std::time::{Duration, Instant};
+use chrono::Local;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use futures::StreamExt;
+use unicode_width::UnicodeWidthChar;
+
+use timeracker_common::{build_report, format_duration, topic_path, DurationStyle, Grouping, Period, ReportOptions, TimeRackerClient,
+                        TimeTrackingInterval, TimeTrackingState};
+use crate::local_time;
+use crate::output::CliError;
+use crate::tree::{render_tree_rows, TreeOptions};
+
+// Full-screen view of the topic tree, with a live timer for the running topic and today's totals.
+// The line protocol has no notifications, so the state is polled with GET_STATE (and
+// GET_INTERVALS for today's totals); the running topic ticks locally in between.
+
+pub struct TuiOptions {
+    pub server: String,
+    pub duration_style: DurationStyle,
+    // How often the state is fetched again from the core
+    pub refresh: Duration,
+}
+
+const HELP: &str = "↑↓ select  enter switch  n new subtopic  N new topic  r rename  i idle  o off  q quit";
+
+// Lines above the tree: title, running topic, blank
+const HEADER_LINES: usize = 3;
+// Lines below the tree: blank, message or prompt, help
+const FOOTER_LINES: usize = 3;
+
+enum Prompt {
+    Create { parent_id: u64 },
+    Rename { id: u64 },
+}
+
+struct Input {
+    prompt: Prompt,
+    text: String,
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+struct App {
+    options: TuiOptions,
+    state: Option<TimeTrackingState>,
+    intervals: Vec<TimeTrackingInterval>,
+    // Duration of the current topic when the state was fetched, and when that was
+    fetched_duration: u64,
+    fetched_at: Instant,
+    // Topic ids, in the order of the tree lines
+    rows: Vec<u64>,
+    selected: usize,
+    // First tree line shown, when the tree is taller than the screen
+    scroll: usize,
+    input: Option<Input>,
+    message: String,
+}
+
+// Puts the terminal back in its normal state, even when leaving on an error or a panic
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter(stdout: &mut Stdout) -> Result<TerminalGuard, CliError> {
+        terminal::enable_raw_mode().map_err(terminal_error)?;
+        execute!(stdout, EnterAlternateScreen, Hide).map_err(terminal_error)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(std::io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub async fn run(client: &mut TimeRackerClient, options: TuiOptions) -> Result<(), CliError> {
+    let mut stdout = std::io::stdout();
+    let mut app = App::new(options);
+    app.refresh(client).await;
+
+    let _guard = TerminalGuard::enter(&mut stdout)?;
+    let mut events = EventStream::new();
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    let mut poll = tokio::time::interval(app.options.refresh);
+
+    loop {
+        app.draw(&mut stdout)?;
+        tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) => {
+                    if let Flow::Quit = app.handle_key(key, client).await {
+                        return Ok(());
+                    }
+                }
+                Some(Ok(_)) => (),
+                Some(Err(e)) => return Err(terminal_error(e)),
+                None => return Ok(()),
+            },
+            _ = tick.tick() => app.tick(),
+            _ = poll.tick() => app.refresh(client).await,
+        }
+    }
+}
+
+impl App {
+    fn new(options: TuiOptions) -> App {
+        App {
+            options,
+            state: None,
+            intervals: vec![],
+            fetched_duration: 0,
+            fetched_at: Instant::now(),
+            rows: vec![],
+            selected: 0,
+            scroll: 0,
+            input: None,
+            message: String::new(),
+        }
+    }
+
+    async fn refresh(&mut self, client: &mut TimeRackerClient) {
+        let fetched = match client.get_state().await {
+            Ok(state) => client.get_intervals_between(Some(local_time::start_of_today()), None).await.map(|intervals| (state, intervals)),
+            Err(e) => Err(e),
+        };
+        let (state, intervals) = match fetched {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                self.message = format!("Core unreachable: {}", e);
+                return;
+            }
+        };
+
+        let selected_id = self.rows.get(self.selected).copied();
+        self.fetched_duration = state.topics_tree.iter()
+            .find(|topic| topic.id == state.current_topic_id)
+            .map_or(0, |topic| topic.duration);
+        self.fetched_at = Instant::now();
+        self.rows = render_tree_rows(&state, &self.tree_options()).into_iter().map(|(id, _)| id).collect();
+        self.state = Some(state);
+        self.intervals = intervals;
+
+        // Stays on the same topic when others appear or vanish above it
+        if let Some(index) = selected_id.and_then(|id| self.rows.iter().position(|row| *row == id)) {
+            self.selected = index;
+        }
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+    }
+
+    // Moves the running topic forward by the time elapsed since the state was fetched
+    fn tick(&mut self) {
+        let elapsed = self.fetched_at.elapsed().as_secs();
+        let fetched_duration = self.fetched_duration;
+        if let Some(state) = self.state.as_mut() {
+            let current_topic_id = state.current_topic_id;
+            if let Some(topic) = state.topics_tree.iter_mut().find(|topic| topic.id == current_topic_id) {
+                topic.duration = fetched_duration + elapsed;
+            }
+        }
+    }
+
+    fn tree_options(&self) -> TreeOptions {
+        TreeOptions { depth: None, highlight: false, duration_style: self.options.duration_style }
+    }
+
+    async fn handle_key(&mut self, key: KeyEvent, client: &mut TimeRackerClient) -> Flow {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Flow::Quit;
+        }
+        if self.input.is_some() {
+            self.handle_input_key(key, client).await;
+            return Flow::Continue;
+        }
+
+        let selected_id = self.rows.get(self.selected).copied();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Flow::Quit,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.rows.len().saturating_sub(1))
+            }
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = self.rows.len().saturating_sub(1),
+            KeyCode::Enter => {
+                if let Some(id) = selected_id {
+                    self.switch_topic(id, client).await;
+                }
+            }
+            KeyCode::Char('i') => self.switch_topic(1, client).await,
+            KeyCode::Char('o') => self.switch_topic(0, client).await,
+            KeyCode::Char('n') => {
+                // Idle cannot have subtopics, "n" on it creates a top-level topic
+                let parent_id = selected_id.filter(|id| *id != 1).unwrap_or(0);
+                self.input = Some(Input { prompt: Prompt::Create { parent_id }, text: String::new() });
+            }
+            KeyCode::Char('N') => {
+                self.input = Some(Input { prompt: Prompt::Create { parent_id: 0 }, text: String::new() });
+            }
+            KeyCode::Char('r') => match selected_id {
+                Some(id) if id > 1 => {
+                    let name = self.topic_name(id);
+                    self.input = Some(Input { prompt: Prompt::Rename { id }, text: name });
+                }
+                _ => self.message = "OFF and Idle cannot be renamed".to_string(),
+            },
+            _ => (),
+        }
+        Flow::Continue
+    }
+
+    async fn handle_input_key(&mut self, key: KeyEvent, client: &mut TimeRackerClient) {
+        let input = match self.input.as_mut() {
+            Some(input) => input,
+            None => return,
+        };
+        match key.code {
+            KeyCode::Esc => self.input = None,
+            KeyCode::Backspace => { input.text.pop(); }
+            KeyCode::Char(c) => input.text.push(c),
+            KeyCode::Enter => {
+                if let Some(input) = self.input.take() {
+                    let name = input.text.trim().to_string();
+                    match input.prompt {
+                        Prompt::Create { parent_id } => self.create_topic(&name, parent_id, client).await,
+                        Prompt::Rename { id } => self.rename_topic(id, &name, client).await,
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    async fn switch_topic(&mut self, id: u64, client: &mut TimeRackerClient) {
+        self.message = match client.switch_topic(id).await {
+            Ok(details) => details,
+            Err(e) => format!("Could not switch: {}", e),
+        };
+        self.refresh(client).await;
+    }
+
+    async fn create_topic(&mut self, name: &str, parent_id: u64, client: &mut TimeRackerClient) {
+        if let Err(e) = client.create_topic(name, parent_id).await {
+            self.message = format!("Could not create {}: {}", name, e);
+            return;
+        }
+        self.message = format!("Created topic {}", name);
+        self.refresh(client).await;
+
+        // Selects the new topic, ready to be switched to
+        let created_id = self.state.as_ref().and_then(|state| {
+            state.topics_tree.iter().find(|topic| topic.parent_id == parent_id && topic.name == name).map(|topic| topic.id)
+        });
+        if let Some(index) = created_id.and_then(|id| self.rows.iter().position(|row| *row == id)) {
+            self.selected = index;
+        }
+    }
+
+    async fn rename_topic(&mut self, id: u64, name: &str, client: &mut TimeRackerClient) {
+        // Parent and duration are sent back as they are now, the duration running if the topic is current
+        let state = match client.get_state().await {
+            Ok(state) => state,
+            Err(e) => {
+                self.message = format!("Could not rename: {}", e);
+                return;
+            }
+        };
+        let topic = match state.topics_tree.iter().find(|topic| topic.id == id) {
+            Some(topic) => topic,
+            None => {
+                self.message = format!("Topic {} no longer exists", id);
+                return;
+            }
+        };
+
+        self.message = match client.update_topic(id, name, topic.parent_id, topic.duration).await {
+            Ok(_) => format!("Renamed {} to {}", topic.name, name),
+            Err(e) => format!("Could not rename: {}", e),
+        };
+        self.refresh(client).await;
+    }
+
+    fn topic_name(&self, id: u64) -> String {
+        self.state.as_ref()
+            .and_then(|state| state.topics_tree.iter().find(|topic| topic.id == id))
+            .map_or_else(String::new, |topic| topic.name.clone())
+    }
+
+    // Seconds spent on each topic since local midnight, running interval included,
+    // as the daily report of the CLI and the HTTP API count them
+    fn today_totals(&self, now: u64) -> HashMap<u64, u64> {
+        let state = match self.state.as_ref() {
+            Some(state) => state,
+            None => return HashMap::new(),
+        };
+        let mut options = ReportOptions::new(now);
+        options.period = Period::Day;
+        options.grouping = Grouping::Topic;
+        options.since = Some(local_time::start_of_today());
+
+        let mut totals = HashMap::new();
+        for period in build_report(state, &self.intervals, &options).periods {
+            for row in period.rows {
+                if let Some(id) = row.topic_id {
+                    *totals.entry(id).or_insert(0) += row.duration;
+                }
+            }
+        }
+        totals
+    }
+
+    fn draw(&mut self, stdout: &mut Stdout) -> Result<(), CliError> {
+        let (width, height) = terminal::size().map_err(terminal_error)?;
+        let (width, height) = (width as usize, height as usize);
+        let now = Local::now();
+        let unix_now = now.timestamp().max(0) as u64;
+        let duration_style = self.options.duration_style;
+        let format = |secs: u64| format_duration(secs, duration_style);
+
+        let tree_height = height.saturating_sub(HEADER_LINES + FOOTER_LINES).max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + tree_height {
+            self.scroll = self.selected + 1 - tree_height;
+        }
+
+        let mut lines: Vec<(String, bool)> = vec![];
+        lines.push((format!(" TimeRacker  {}  {}", self.options.server, now.format("%a %d %b %H:%M:%S")), true));
+
+        let today = self.today_totals(unix_now);
+        match self.state.as_ref() {
+            None => {
+                lines.push((" Waiting for the core...".to_string(), false));
+                lines.push((String::new(), false));
+            }
+            Some(state) => {
+                let worked_today: u64 = today.iter().filter(|(id, _)| **id != 1).map(|(_, secs)| secs).sum();
+                let running = match state.current_topic_id {
+                    0 => " Tracking is OFF".to_string(),
+                    id => format!(" ▶ {}  {}", topic_path(state, id),
+                                  format_duration(unix_now.saturating_sub(state.current_topic_since), DurationStyle::Clock)),
+                };
+                lines.push((format!("{}    today {}", running, format(worked_today)), false));
+                lines.push((String::new(), false));
+
+                let rows = render_tree_rows(state, &self.tree_options());
+                let today_width = rows.iter().map(|(id, _)| today.get(id).map_or(0, |secs| format(*secs).len())).max().unwrap_or(0);
+                for (index, (id, line)) in rows.iter().enumerate().skip(self.scroll).take(tree_height) {
+                    let today_secs = today.get(id).map_or_else(String::new, |secs| format(*secs));
+                    lines.push((format!("{}  {:>width$}", line, today_secs, width = today_width), index == self.selected));
+                }
+            }
+        }
+
+        while lines.len() < height.saturating_sub(FOOTER_LINES - 1) {
+            lines.push((String::new(), false));
+        }
+        let status = match self.input.as_ref() {
+            Some(Input { prompt: Prompt::Create { parent_id: 0 }, text }) => format!(" New topic: {}_", text),
+            Some(Input { prompt: Prompt::Create { parent_id }, text }) => {
+                let parent = self.state.as_ref().map_or_else(String::new, |state| topic_path(state, *parent_id));
+                format!(" New subtopic of {}: {}_", parent, text)
+            }
+            Some(Input { prompt: Prompt::Rename { .. }, text }) => format!(" Rename to: {}_", text),
+            None => format!(" {}", self.message),
+        };
+        lines.push((status, false));
+        lines.push((format!(" {}", HELP), false));
+
+        queue!(stdout, Clear(ClearType::All)).map_err(terminal_error)?;
+        for (y, (line, reversed)) in lines.iter().take(height).enumerate() {
+            queue!(stdout, MoveTo(0, y as u16)).map_err(terminal_error)?;
+            if *reversed {
+                queue!(stdout, SetAttribute(Attribute::Reverse), Print(fit(line, width)), SetAttribute(Attribute::Reset))
+            } else {
+                queue!(stdout, Print(fit(line, width)))
+            }.map_err(terminal_error)?;
+        }
+        stdout.flush().map_err(terminal_error)
+    }
+}
+
+// Cuts `line` to `width` columns, padded with spaces so that reversed lines span the screen
+fn fit(line: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in line.chars() {
+        let c_width = c.width().unwrap_or(0);
+        if used + c_width > width {
+            break;
+        }
+        fitted.push(c);
+        used += c_width;
+    }
+    fitted.push_str(&" ".repeat(width - used));
+    fitted
+}
+
+fn terminal_error(e: impl std::fmt::Display) -> CliError {
+    CliError { kind: "terminal".to_string(), code: None, message: e.to_string() }
+}
@@ -0,0 +1,236 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time;
+use tracing::{error, info, Span};
+
+use timeracker_common::{ClientRequest, ProtocolError, ResponseToClient, StateChangeEvent, TimeTracker, TimeTrackingInterval, TopicSwitch};
+
+use crate::storage::{append_interval, save_state};
+
+pub const STATE_ACTOR_QUEUE_SIZE: usize = 64;
+// Subscribers lagging further behind than this miss events
+pub const STATE_EVENTS_QUEUE_SIZE: usize = 64;
+pub const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// A request travelling from a connection task to the state actor,
+// along with the channel the actor answers through.
+// The span of the sender is carried along so that the actor logs under the right connection.
+struct StateCommand {
+    request: ClientRequest,
+    reply_to: oneshot::Sender<ResponseToClient>,
+    span: Span,
+}
+
+// Cheap, cloneable handle given to every connection task.
+// The state itself is only ever touched by the actor task.
+#[derive(Clone)]
+pub struct StateHandle {
+    sender: mpsc::Sender<StateCommand>,
+    events: broadcast::Sender<StateChangeEvent>,
+}
+
+impl StateHandle {
+    // Every change made to the state after this call is received, in order
+    pub fn subscribe(&self) -> broadcast::Receiver<StateChangeEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn request(&self, request: ClientRequest) -> ResponseToClient {
+        let (reply_to, reply) = oneshot::channel();
+
+        let command = StateCommand { request, reply_to, span: Span::current() };
+        if self.sender.send(command).await.is_err() {
+            return ProtocolError::Internal { msg: "state actor is not running".to_string() }.into();
+        }
+
+        match reply.await {
+            Ok(response) => response,
+            Err(_) => ProtocolError::Internal { msg: "state actor dropped the request".to_string() }.into(),
+        }
+    }
+}
+
+// Settings the actor needs besides the state itself
+pub struct StateActorOptions {
+    pub state_file_path: PathBuf,
+    pub intervals_file_path: PathBuf,
+    pub idle_after: Option<Duration>,
+    pub off_after: Option<Duration>,
+}
+
+struct StateActor {
+    tracker: TimeTracker,
+    options: StateActorOptions,
+    events: broadcast::Sender<StateChangeEvent>,
+}
+
+// Spawns the task owning the state, and returns the handle used to talk to it.
+// Requests are processed one at a time, in the order they were received.
+pub fn spawn_state_actor(tracker: TimeTracker, options: StateActorOptions) -> StateHandle {
+    let (sender, receiver) = mpsc::channel(STATE_ACTOR_QUEUE_SIZE);
+    let (events, _) = broadcast::channel(STATE_EVENTS_QUEUE_SIZE);
+    let actor = StateActor { tracker, options, events: events.clone() };
+    tokio::spawn(actor.run(receiver));
+    StateHandle { sender, events }
+}
+
+impl StateActor {
+    async fn run(mut self, mut receiver: mpsc::Receiver<StateCommand>) {
+        let mut idle_check = time::interval(IDLE_CHECK_INTERVAL);
+        let mut autosave = time::interval(AUTOSAVE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                command = receiver.recv() => match command {
+                    Some(command) => self.dispatch(command),
+                    None => break,
+                },
+                _ = idle_check.tick() => self.check_idle(),
+                _ = autosave.tick() => self.persist(),
+            }
+        }
+
+        self.persist();
+    }
+
+    fn dispatch(&mut self, command: StateCommand) {
+        let StateCommand { request, reply_to, span } = command;
+        let _entered = span.enter();
+
+        // A bug in one request handler must not take down the daemon for every client
+        let response = panic::catch_unwind(AssertUnwindSafe(|| self.process_request(request)))
+            .unwrap_or_else(|_| {
+                error!("Panic while processing request, state kept as is");
+                ProtocolError::Internal { msg: "panic while processing request".to_string() }.into()
+            });
+
+        // The client may have gone away in the meantime, nothing to do then
+        let _ = reply_to.send(response);
+    }
+
+    fn process_request(&mut self, request: ClientRequest) -> ResponseToClient {
+        if request.is_activity() {
+            self.tracker.record_activity();
+        }
+
+        match request {
+            ClientRequest::GetState{ } =>  {
+                let response_string = serde_json::to_string(self.tracker.state()).unwrap();
+                ResponseToClient::State {value: response_string}
+            },
+
+            ClientRequest::Bye{ } =>  {
+                ResponseToClient::Bye { }
+            },
+
+            ClientRequest::Terminate{ } =>  {
+                info!("Termination requested");
+                self.tracker.accumulate();
+                let closed_interval = self.tracker.close_current_interval();
+                self.save_interval(closed_interval);
+                self.persist();
+                ResponseToClient::Terminating { }
+            },
+
+            ClientRequest::SwitchTopic { id } => {
+                match self.switch_topic(id) {
+                    Ok(new_topic_name) => ResponseToClient::Success {details: format!("Switched topic to {}", new_topic_name)},
+                    Err(e) => e.into()
+                }
+            },
+
+            ClientRequest::CreateTopic { name, parent_id } => {
+                match self.tracker.create_topic(&name, parent_id) {
+                    Ok((id, event)) => {
+                        info!(topic_id = id, topic_name = %name, parent_id, "Created topic");
+                        self.persist();
+                        self.publish(event);
+                        ResponseToClient::Success {details: format!("Created topic {} with id {}", name, id)}
+                    }
+                    Err(e) => e.into()
+                }
+            },
+
+            ClientRequest::UpdateTopic { id, name, parent_id, duration } => {
+                match self.tracker.update_topic(id, &name, parent_id, duration) {
+                    Ok(event) => {
+                        info!(topic_id = id, topic_name = %name, parent_id, duration, "Updated topic");
+                        self.persist();
+                        self.publish(event);
+                        ResponseToClient::Success {details: format!("Updated topic {}", id)}
+                    }
+                    Err(e) => e.into()
+                }
+            },
+
+            ClientRequest::GetIntervals { since, until } => {
+                ResponseToClient::Intervals { intervals: self.tracker.intervals_between(since, until) }
+            },
+
+            ClientRequest::SetNote { note } => {
+                match self.tracker.set_note(note) {
+                    Ok(event) => {
+                        info!(topic_id = self.tracker.current_topic_id(), "Set note");
+                        self.publish(event);
+                        ResponseToClient::Success {details: "Note set".to_string()}
+                    }
+                    Err(e) => e.into()
+                }
+            },
+
+            ClientRequest::Activity { } => {
+                ResponseToClient::Success {details: "Activity recorded".to_string()}
+            },
+
+            other => ProtocolError::Unsupported { command: other.name() }.into(),
+        }
+    }
+
+    // Returns the name of the new topic
+    fn switch_topic(&mut self, id: u64) -> Result<String, ProtocolError> {
+        let switch = self.tracker.switch_topic(id)?;
+        Ok(self.apply_switch(switch))
+    }
+
+    // Logs, persists and publishes a switch made by the tracker
+    fn apply_switch(&mut self, switch: TopicSwitch) -> String {
+        let TopicSwitch { topic_name, closed_interval, event } = switch;
+        info!(topic_id = self.tracker.current_topic_id(), topic_name = %topic_name, "Switched topic");
+
+        self.save_interval(closed_interval);
+        self.persist();
+        self.publish(event);
+        topic_name
+    }
+
+    fn check_idle(&mut self) {
+        // Taken before the switch, which counts as activity
+        let idle_secs = self.tracker.since_last_activity().as_secs();
+        if let Some(switch) = self.tracker.check_idle(self.options.idle_after, self.options.off_after) {
+            info!(idle_secs, "Threshold reached without activity");
+            self.apply_switch(switch);
+        }
+    }
+
+    fn publish(&self, event: StateChangeEvent) {
+        // Fails only when nobody is subscribed, which is fine
+        let _ = self.events.send(event);
+    }
+
+    fn save_interval(&self, interval: Option<TimeTrackingInterval>) {
+        if let Some(interval) = interval {
+            if let Err(e) = append_interval(&self.options.intervals_file_path, &interval) {
+                error!(error = %e, path = %self.options.intervals_file_path.display(), "Could not save interval");
+            }
+        }
+    }
+
+    fn persist(&mut self) {
+        if let Err(e) = save_state(&self.options.state_file_path, self.tracker.state()) {
+            error!(error = %e, path = %self.options.state_file_path.display(), "Could not save state");
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread::sleep;
+
+use crate::client::{decode_response, expect_bye, expect_intervals, expect_state, expect_success,
+                    expect_terminating, is_retriable, server_error_to_err};
+use crate::{ClientError, ClientOptions, ClientRequest, ResponseToClient, TimeTrackingInterval, TimeTrackingState};
+
+// Synchronous counterpart of TimeRackerClient, over std::net, for programs without a tokio runtime.
+// Same options, same errors, same reconnection rules.
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+pub struct BlockingTimeRackerClient {
+    options: ClientOptions,
+    connection: Option<Connection>,
+}
+
+impl BlockingTimeRackerClient {
+    pub fn connect(options: ClientOptions) -> Result<BlockingTimeRackerClient, ClientError> {
+        let mut client = BlockingTimeRackerClient { options, connection: None };
+        client.reconnect()?;
+        Ok(client)
+    }
+
+    pub fn options(&self) -> &ClientOptions {
+        &self.options
+    }
+
+    pub fn get_state(&mut self) -> Result<TimeTrackingState, ClientError> {
+        expect_state(self.request(ClientRequest::GetState {})?)
+    }
+
+    pub fn switch_topic(&mut self, id: u64) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::SwitchTopic { id })
+    }
+
+    // Names are single words, anything else fails with ClientError::InvalidRequest before being sent
+    pub fn create_topic(&mut self, name: &str, parent_id: u64) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::CreateTopic { name: name.to_string(), parent_id })
+    }
+
+    pub fn update_topic(&mut self, id: u64, name: &str, parent_id: u64, duration: u64) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::UpdateTopic { id, name: name.to_string(), parent_id, duration })
+    }
+
+    pub fn delete_topic(&mut self, id: u64) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::DeleteTopic { id })
+    }
+
+    pub fn get_intervals(&mut self) -> Result<Vec<TimeTrackingInterval>, ClientError> {
+        self.get_intervals_between(None, None)
+    }
+
+    // Intervals overlapping [since, until), in seconds since the Unix epoch
+    pub fn get_intervals_between(&mut self, since: Option<u64>, until: Option<u64>) -> Result<Vec<TimeTrackingInterval>, ClientError> {
+        expect_intervals(self.request(ClientRequest::GetIntervals { since, until })?)
+    }
+
+    // Note of the running interval, None to clear it. Line breaks cannot be sent and are replaced with spaces.
+    pub fn set_note(&mut self, note: Option<&str>) -> Result<String, ClientError> {
+        let note = note.map(|note| note.replace(['\r', '\n'], " "));
+        self.request_success(ClientRequest::SetNote { note })
+    }
+
+    // Tells the core the user is at work, putting off the fall back to Idle
+    pub fn report_activity(&mut self) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::Activity {})
+    }
+
+    pub fn auth(&mut self, token: &str) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::Auth { token: token.to_string() })
+    }
+
+    pub fn bye(&mut self) -> Result<(), ClientError> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        let response = self.exchange(&ClientRequest::Bye {});
+        self.connection = None;
+        expect_bye(response.and_then(server_error_to_err)?)
+    }
+
+    pub fn terminate(&mut self) -> Result<(), ClientError> {
+        let response = self.request(ClientRequest::Terminate {});
+        self.connection = None;
+        expect_terminating(response?)
+    }
+
+    pub fn request(&mut self, request: ClientRequest) -> Result<ResponseToClient, ClientError> {
+        if self.connection.is_none() {
+            self.reconnect()?;
+        }
+
+        let response = match self.exchange(&request) {
+            Err(e) if e.is_transport() && is_retriable(&request) => {
+                self.connection = None;
+                self.reconnect()?;
+                self.exchange(&request)
+            }
+            other => other,
+        };
+
+        if let Err(e) = &response {
+            if e.is_transport() {
+                self.connection = None;
+            }
+        }
+        response.and_then(server_error_to_err)
+    }
+
+    fn request_success(&mut self, request: ClientRequest) -> Result<String, ClientError> {
+        let request_name = request.name();
+        expect_success(request_name, self.request(request)?)
+    }
+
+    fn reconnect(&mut self) -> Result<(), ClientError> {
+        let mut attempt = 0;
+        loop {
+            match self.open_connection() {
+                Ok(connection) => {
+                    self.connection = Some(connection);
+                    return Ok(());
+                }
+                Err(e @ ClientError::Server(_)) | Err(e @ ClientError::InvalidRequest(_)) => return Err(e),
+                Err(e) if attempt >= self.options.reconnect_attempts => return Err(e),
+                Err(_) => {
+                    attempt += 1;
+                    sleep(self.options.reconnect_delay);
+                }
+            }
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection, ClientError> {
+        let stream = connect_any(&self.options)?;
+        stream.set_read_timeout(Some(self.options.request_timeout))?;
+        stream.set_write_timeout(Some(self.options.request_timeout))?;
+
+        let mut connection = Connection { reader: BufReader::new(stream.try_clone()?), writer: stream };
+
+        if let Some(token) = &self.options.token {
+            let response = send_and_receive(&mut connection, &ClientRequest::Auth { token: token.clone() })?;
+            expect_success("AUTH", server_error_to_err(response)?)?;
+        }
+        Ok(connection)
+    }
+
+    fn exchange(&mut self, request: &ClientRequest) -> Result<ResponseToClient, ClientError> {
+        match self.connection.as_mut() {
+            Some(connection) => send_and_receive(connection, request),
+            None => Err(ClientError::ConnectionClosed),
+        }
+    }
+}
+
+// Tries every address the server name resolves to, like TcpStream::connect, but with a timeout
+fn connect_any(options: &ClientOptions) -> Result<TcpStream, ClientError> {
+    let mut last_error = None;
+    for addr in options.server.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, options.connect_timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => timeout_or_io(e),
+        None => ClientError::Io(io::Error::new(io::ErrorKind::NotFound, "server address did not resolve")),
+    })
+}
+
+fn send_and_receive(connection: &mut Connection, request: &ClientRequest) -> Result<ResponseToClient, ClientError> {
+    request.validate().map_err(ClientError::InvalidRequest)?;
+    writeln!(connection.writer, "{}", request.emit()).map_err(timeout_or_io)?;
+
+    let mut line = String::new();
+    match connection.reader.read_line(&mut line).map_err(timeout_or_io)? {
+        0 => Err(ClientError::ConnectionClosed),
+        _ => decode_response(line.trim_end()),
+    }
+}
+
+fn timeout_or_io(e: io::Error) -> ClientError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+        _ => ClientError::Io(e),
+    }
+}
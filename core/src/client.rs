@@ -0,0 +1,329 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use futures::SinkExt;
+use tokio::net::TcpStream;
+use tokio::stream::StreamExt;
+use tokio::time::{sleep, timeout};
+use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
+
+use crate::{ClientRequest, ErrorKind, ProtocolError, ResponseToClient, TimeTrackingInterval, TimeTrackingState};
+
+// Async client for the line protocol of timeracker_core. Each method sends one request
+// and waits for its response; the connection is re-established transparently when it drops.
+
+pub const DEFAULT_SERVER: &str = "localhost:45862";
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub server: String,
+    // Sent with AUTH on every (re)connection, when the core requires it
+    pub token: Option<String>,
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    // Further connection attempts after the first one fails, 0 to never reconnect
+    pub reconnect_attempts: u32,
+    pub reconnect_delay: Duration,
+}
+
+impl ClientOptions {
+    pub fn new() -> ClientOptions {
+        ClientOptions {
+            server: DEFAULT_SERVER.to_string(),
+            token: None,
+            connect_timeout: Duration::from_secs(2),
+            request_timeout: Duration::from_secs(5),
+            reconnect_attempts: 3,
+            reconnect_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+impl ::std::default::Default for ClientOptions {
+    fn default() -> Self { Self::new() }
+}
+
+// Error response sent back by the core
+#[derive(Debug, Clone)]
+pub struct ServerError {
+    pub error_code: u64,
+    pub kind: ErrorKind,
+    pub msg: String,
+    pub field: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Timeout,
+    ConnectionClosed,
+    // The core answered with something that is not valid JSON for the expected type
+    Decode(String),
+    // The core answered with a response of the wrong type for the request
+    UnexpectedResponse { request: &'static str },
+    // The request cannot be sent as is, it was not sent at all
+    InvalidRequest(ProtocolError),
+    Server(ServerError),
+}
+
+impl ClientError {
+    // Whether the connection is unusable after this error
+    pub(crate) fn is_transport(&self) -> bool {
+        matches!(self, ClientError::Io(_) | ClientError::Timeout | ClientError::ConnectionClosed)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "connection error: {}", e),
+            ClientError::Timeout => write!(f, "timed out waiting for the core"),
+            ClientError::ConnectionClosed => write!(f, "connection closed by the core"),
+            ClientError::Decode(msg) => write!(f, "invalid response from the core: {}", msg),
+            ClientError::UnexpectedResponse { request } => write!(f, "unexpected response to {}", request),
+            ClientError::InvalidRequest(e) => write!(f, "{}", e),
+            ClientError::Server(e) => write!(f, "{} (error {})", e.msg, e.error_code),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self { ClientError::Io(e) }
+}
+
+impl From<LinesCodecError> for ClientError {
+    fn from(e: LinesCodecError) -> Self {
+        match e {
+            LinesCodecError::Io(e) => ClientError::Io(e),
+            LinesCodecError::MaxLineLengthExceeded => ClientError::Decode("line too long".to_string()),
+        }
+    }
+}
+
+pub struct TimeRackerClient {
+    options: ClientOptions,
+    lines: Option<Framed<TcpStream, LinesCodec>>,
+}
+
+impl TimeRackerClient {
+    // Connects (and authenticates) right away, so that an unreachable core is reported early
+    pub async fn connect(options: ClientOptions) -> Result<TimeRackerClient, ClientError> {
+        let mut client = TimeRackerClient { options, lines: None };
+        client.reconnect().await?;
+        Ok(client)
+    }
+
+    pub fn options(&self) -> &ClientOptions {
+        &self.options
+    }
+
+    // Applied from the next reconnection on
+    pub fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.options
+    }
+
+    pub async fn get_state(&mut self) -> Result<TimeTrackingState, ClientError> {
+        expect_state(self.request(ClientRequest::GetState {}).await?)
+    }
+
+    pub async fn switch_topic(&mut self, id: u64) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::SwitchTopic { id }).await
+    }
+
+    // Names are single words, anything else fails with ClientError::InvalidRequest before being sent
+    pub async fn create_topic(&mut self, name: &str, parent_id: u64) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::CreateTopic { name: name.to_string(), parent_id }).await
+    }
+
+    pub async fn update_topic(&mut self, id: u64, name: &str, parent_id: u64, duration: u64) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::UpdateTopic { id, name: name.to_string(), parent_id, duration }).await
+    }
+
+    pub async fn delete_topic(&mut self, id: u64) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::DeleteTopic { id }).await
+    }
+
+    pub async fn get_intervals(&mut self) -> Result<Vec<TimeTrackingInterval>, ClientError> {
+        self.get_intervals_between(None, None).await
+    }
+
+    // Intervals overlapping [since, until), in seconds since the Unix epoch
+    pub async fn get_intervals_between(&mut self, since: Option<u64>, until: Option<u64>) -> Result<Vec<TimeTrackingInterval>, ClientError> {
+        expect_intervals(self.request(ClientRequest::GetIntervals { since, until }).await?)
+    }
+
+    // Note of the running interval, None to clear it. Line breaks cannot be sent and are replaced with spaces.
+    pub async fn set_note(&mut self, note: Option<&str>) -> Result<String, ClientError> {
+        let note = note.map(|note| note.replace(['\r', '\n'], " "));
+        self.request_success(ClientRequest::SetNote { note }).await
+    }
+
+    // Tells the core the user is at work, putting off the fall back to Idle
+    pub async fn report_activity(&mut self) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::Activity {}).await
+    }
+
+    // Only needed to switch tokens, the configured one is sent on every connection
+    pub async fn auth(&mut self, token: &str) -> Result<String, ClientError> {
+        self.request_success(ClientRequest::Auth { token: token.to_string() }).await
+    }
+
+    // Ends the session cleanly. Does nothing if the connection is already gone.
+    pub async fn bye(&mut self) -> Result<(), ClientError> {
+        if self.lines.is_none() {
+            return Ok(());
+        }
+        let response = self.exchange(&ClientRequest::Bye {}).await;
+        self.lines = None;
+        expect_bye(response.and_then(server_error_to_err)?)
+    }
+
+    pub async fn terminate(&mut self) -> Result<(), ClientError> {
+        let response = self.request(ClientRequest::Terminate {}).await;
+        self.lines = None;
+        expect_terminating(response?)
+    }
+
+    // Sends any request and returns its response, error responses being turned into ClientError::Server.
+    // Requests that are safe to repeat are resent once the connection is re-established.
+    pub async fn request(&mut self, request: ClientRequest) -> Result<ResponseToClient, ClientError> {
+        if self.lines.is_none() {
+            self.reconnect().await?;
+        }
+
+        let response = match self.exchange(&request).await {
+            Err(e) if e.is_transport() && is_retriable(&request) => {
+                self.lines = None;
+                self.reconnect().await?;
+                self.exchange(&request).await
+            }
+            other => other,
+        };
+
+        if let Err(e) = &response {
+            if e.is_transport() {
+                self.lines = None;
+            }
+        }
+        response.and_then(server_error_to_err)
+    }
+
+    async fn request_success(&mut self, request: ClientRequest) -> Result<String, ClientError> {
+        let request_name = request.name();
+        expect_success(request_name, self.request(request).await?)
+    }
+
+    async fn reconnect(&mut self) -> Result<(), ClientError> {
+        let mut attempt = 0;
+        loop {
+            match self.open_connection().await {
+                Ok(lines) => {
+                    self.lines = Some(lines);
+                    break;
+                }
+                // A rejected token will not get any better by retrying
+                Err(e @ ClientError::Server(_)) | Err(e @ ClientError::InvalidRequest(_)) => return Err(e),
+                Err(e) if attempt >= self.options.reconnect_attempts => return Err(e),
+                Err(_) => {
+                    attempt += 1;
+                    sleep(self.options.reconnect_delay).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn open_connection(&self) -> Result<Framed<TcpStream, LinesCodec>, ClientError> {
+        let stream = timeout(self.options.connect_timeout, TcpStream::connect(&self.options.server)).await
+            .map_err(|_| ClientError::Timeout)??;
+        let mut lines = Framed::new(stream, LinesCodec::new());
+
+        if let Some(token) = &self.options.token {
+            let auth = ClientRequest::Auth { token: token.clone() };
+            let response = send_and_receive(&mut lines, &auth, self.options.request_timeout).await?;
+            expect_success("AUTH", server_error_to_err(response)?)?;
+        }
+        Ok(lines)
+    }
+
+    async fn exchange(&mut self, request: &ClientRequest) -> Result<ResponseToClient, ClientError> {
+        let request_timeout = self.options.request_timeout;
+        match self.lines.as_mut() {
+            Some(lines) => send_and_receive(lines, request, request_timeout).await,
+            None => Err(ClientError::ConnectionClosed),
+        }
+    }
+}
+
+async fn send_and_receive(lines: &mut Framed<TcpStream, LinesCodec>,
+                          request: &ClientRequest,
+                          request_timeout: Duration) -> Result<ResponseToClient, ClientError> {
+    request.validate().map_err(ClientError::InvalidRequest)?;
+    let exchange = async {
+        lines.send(request.emit()).await?;
+        match lines.next().await {
+            Some(line) => decode_response(&line?),
+            None => Err(ClientError::ConnectionClosed),
+        }
+    };
+    timeout(request_timeout, exchange).await.map_err(|_| ClientError::Timeout)?
+}
+
+// Creating a topic twice would leave a duplicate behind, and TERMINATE may well have
+// been honoured when the connection drops
+pub(crate) fn is_retriable(request: &ClientRequest) -> bool {
+    !matches!(request, ClientRequest::CreateTopic { .. } | ClientRequest::Terminate {} | ClientRequest::Bye {})
+}
+
+// Response handling shared with the blocking client
+
+pub(crate) fn decode_response(line: &str) -> Result<ResponseToClient, ClientError> {
+    serde_json::from_str(line).map_err(|e| ClientError::Decode(e.to_string()))
+}
+
+pub(crate) fn server_error_to_err(response: ResponseToClient) -> Result<ResponseToClient, ClientError> {
+    match response {
+        ResponseToClient::Error { error_code, kind, msg, field } => {
+            Err(ClientError::Server(ServerError { error_code, kind, msg, field }))
+        }
+        response => Ok(response),
+    }
+}
+
+pub(crate) fn expect_state(response: ResponseToClient) -> Result<TimeTrackingState, ClientError> {
+    match response {
+        ResponseToClient::State { value } => serde_json::from_str(&value)
+            .map_err(|e| ClientError::Decode(e.to_string())),
+        _ => Err(ClientError::UnexpectedResponse { request: "GET_STATE" }),
+    }
+}
+
+pub(crate) fn expect_intervals(response: ResponseToClient) -> Result<Vec<TimeTrackingInterval>, ClientError> {
+    match response {
+        ResponseToClient::Intervals { intervals } => Ok(intervals),
+        _ => Err(ClientError::UnexpectedResponse { request: "GET_INTERVALS" }),
+    }
+}
+
+pub(crate) fn expect_success(request: &'static str, response: ResponseToClient) -> Result<String, ClientError> {
+    match response {
+        ResponseToClient::Success { details } => Ok(details),
+        _ => Err(ClientError::UnexpectedResponse { request }),
+    }
+}
+
+pub(crate) fn expect_bye(response: ResponseToClient) -> Result<(), ClientError> {
+    match response {
+        ResponseToClient::Bye {} => Ok(()),
+        _ => Err(ClientError::UnexpectedResponse { request: "BYE" }),
+    }
+}
+
+pub(crate) fn expect_terminating(response: ResponseToClient) -> Result<(), ClientError> {
+    match response {
+        ResponseToClient::Terminating {} => Ok(()),
+        _ => Err(ClientError::UnexpectedResponse { request: "TERMINATE" }),
+    }
+}
@@ -0,0 +1,261 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use clap::Clap;
+use directories::ProjectDirs;
+use serde::Deserialize;
+
+use crate::logging::LoggingOptions;
+
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:45862";
+pub const CONFIG_FILE_NAME: &str = "timeracker_core.toml";
+
+// Command line options. Each one can also be given through the environment,
+// and takes precedence over the configuration file.
+#[derive(Clap)]
+#[clap(version = "0.1", author = "liothique <liothique@liothique.xyz>")]
+pub struct CoreCliOptions {
+    /// Path of the configuration file
+    #[clap(short, long, env = "TIMERACKER_CORE_CONFIG")]
+    config: Option<String>,
+    /// Address to listen on, can be repeated
+    #[clap(short, long, env = "TIMERACKER_LISTEN", use_delimiter = true)]
+    listen: Vec<String>,
+    /// Address to serve the HTTP REST API on, can be repeated
+    #[clap(long, env = "TIMERACKER_HTTP_LISTEN", use_delimiter = true)]
+    http_listen: Vec<String>,
+    /// Origin browsers may call the HTTP REST API from, such as http://localhost:8080, can be repeated
+    #[clap(long, env = "TIMERACKER_HTTP_ALLOWED_ORIGINS", use_delimiter = true)]
+    http_allowed_origin: Vec<String>,
+    /// Address to serve the WebSocket endpoint on, can be repeated
+    #[clap(long, env = "TIMERACKER_WS_LISTEN", use_delimiter = true)]
+    ws_listen: Vec<String>,
+    /// Origin browsers may open the WebSocket endpoint from, such as http://localhost:8080, can be repeated
+    #[clap(long, env = "TIMERACKER_WS_ALLOWED_ORIGINS", use_delimiter = true)]
+    ws_allowed_origin: Vec<String>,
+    /// Directory holding the persisted state and the log files
+    #[clap(long, env = "TIMERACKER_DATA_DIR")]
+    data_dir: Option<String>,
+    /// Topic created on first run, can be repeated; "Work/ClientA" creates ClientA under Work
+    #[clap(long = "default-topic", env = "TIMERACKER_DEFAULT_TOPICS", use_delimiter = true)]
+    default_topics: Vec<String>,
+    /// Switch to Idle after this many seconds without activity (switch, change or ACTIVITY request), 0 to disable
+    #[clap(long, env = "TIMERACKER_IDLE_AFTER_SECS")]
+    idle_after_secs: Option<u64>,
+    /// Switch to OFF after this many seconds in Idle without activity, 0 to disable
+    #[clap(long, env = "TIMERACKER_OFF_AFTER_SECS")]
+    off_after_secs: Option<u64>,
+    /// Log verbosity, or a full filter directive
+    #[clap(long, env = "TIMERACKER_LOG")]
+    log_level: Option<String>,
+    /// Also write logs to a daily-rotated file in this directory
+    #[clap(long, env = "TIMERACKER_LOG_DIR")]
+    log_dir: Option<String>,
+    /// Token clients must present with AUTH before any other command
+    #[clap(long, env = "TIMERACKER_AUTH_TOKEN")]
+    auth_token: Option<String>,
+    /// Address to listen on (kept for compatibility, prefer --listen)
+    addr: Option<String>,
+}
+
+// Layout of the configuration file. Every key is optional.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    server: ServerSection,
+    http: HttpSection,
+    websocket: WebSocketSection,
+    storage: StorageSection,
+    tracking: TrackingSection,
+    log: LogSection,
+    auth: AuthSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    listen: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct HttpSection {
+    listen: Vec<String>,
+    allowed_origins: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct WebSocketSection {
+    listen: Vec<String>,
+    allowed_origins: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct StorageSection {
+    data_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TrackingSection {
+    default_topics: Option<Vec<String>>,
+    idle_after_secs: Option<u64>,
+    off_after_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LogSection {
+    level: Option<String>,
+    file: bool,
+    dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSection {
+    token: Option<String>,
+}
+
+// Effective settings of the core, once command line, environment,
+// configuration file and defaults have been reconciled.
+pub struct Options {
+    pub listen: Vec<String>,
+    // Empty unless the HTTP REST API is enabled
+    pub http_listen: Vec<String>,
+    // Origins whose pages may call the HTTP REST API, none by default
+    pub http_allowed_origins: Vec<String>,
+    // Empty unless the WebSocket endpoint is enabled
+    pub ws_listen: Vec<String>,
+    // Origins whose pages may open the WebSocket endpoint, none by default
+    pub ws_allowed_origins: Vec<String>,
+    // Also holds the pid file, so that two cores never share the same data
+    pub data_dir: PathBuf,
+    // Topics created on first run, besides the built-in OFF and Idle.
+    // Each entry is a path, "Work/ClientA" creates ClientA under Work.
+    pub default_topics: Vec<String>,
+    // Switch to Idle after this long without activity, 0 to disable
+    pub idle_after_secs: u64,
+    // Switch to OFF after this long in Idle, 0 to disable
+    pub off_after_secs: u64,
+    pub log_level: String,
+    pub log_dir: Option<PathBuf>,
+    pub auth_token: Option<String>,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            listen: vec![DEFAULT_LISTEN_ADDR.to_string()],
+            http_listen: vec![],
+            http_allowed_origins: vec![],
+            ws_listen: vec![],
+            ws_allowed_origins: vec![],
+            data_dir: project_dirs().data_dir().to_path_buf(),
+            default_topics: vec!["Work".to_string()],
+            idle_after_secs: 0,
+            off_after_secs: 0,
+            log_level: crate::logging::DEFAULT_LOG_LEVEL.to_string(),
+            log_dir: None,
+            auth_token: None
+        }
+    }
+
+    // Parses the command line and reads the configuration file it points to
+    // (or the default one). Exits on an unreadable or invalid configuration file.
+    pub fn load() -> Options {
+        let cli_options = CoreCliOptions::parse();
+
+        let config_file_path = match &cli_options.config {
+            Some(path) => PathBuf::from(path),
+            None => project_dirs().config_dir().join(CONFIG_FILE_NAME),
+        };
+
+        let config_file = match read_config_file(&config_file_path) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Error while reading config file {} : {}", config_file_path.display(), e);
+                std::process::exit(1);
+            }
+        };
+
+        let mut options = Options::new();
+        options.reconcile(cli_options, config_file);
+        options
+    }
+
+    fn reconcile(&mut self, cli: CoreCliOptions, conf: ConfigFile) {
+        let mut cli_listen = cli.listen;
+        cli_listen.extend(cli.addr);
+        if !cli_listen.is_empty() {
+            self.listen = cli_listen;
+        } else if !conf.server.listen.is_empty() {
+            self.listen = conf.server.listen;
+        }
+        self.http_listen = if !cli.http_listen.is_empty() { cli.http_listen } else { conf.http.listen };
+        self.http_allowed_origins = if !cli.http_allowed_origin.is_empty() { cli.http_allowed_origin } else { conf.http.allowed_origins };
+        self.ws_listen = if !cli.ws_listen.is_empty() { cli.ws_listen } else { conf.websocket.listen };
+        self.ws_allowed_origins = if !cli.ws_allowed_origin.is_empty() { cli.ws_allowed_origin } else { conf.websocket.allowed_origins };
+
+        if let Some(data_dir) = cli.data_dir.map(PathBuf::from).or(conf.storage.data_dir) {
+            self.data_dir = data_dir;
+        }
+
+        if !cli.default_topics.is_empty() {
+            self.default_topics = cli.default_topics;
+        } else if let Some(default_topics) = conf.tracking.default_topics {
+            self.default_topics = default_topics;
+        }
+        self.idle_after_secs = cli.idle_after_secs.or(conf.tracking.idle_after_secs).unwrap_or(self.idle_after_secs);
+        self.off_after_secs = cli.off_after_secs.or(conf.tracking.off_after_secs).unwrap_or(self.off_after_secs);
+
+        if let Some(log_level) = cli.log_level.or(conf.log.level) {
+            self.log_level = log_level;
+        }
+        let default_log_dir = if conf.log.file { Some(self.data_dir.join("logs")) } else { None };
+        self.log_dir = cli.log_dir.map(PathBuf::from).or(conf.log.dir).or(default_log_dir);
+
+        self.auth_token = cli.auth_token.or(conf.auth.token).filter(|token| !token.is_empty());
+    }
+
+    pub fn logging_options(&self) -> LoggingOptions {
+        LoggingOptions {
+            level: self.log_level.clone(),
+            log_dir: self.log_dir.clone()
+        }
+    }
+
+    pub fn state_file_path(&self) -> PathBuf {
+        self.data_dir.join(crate::storage::STATE_FILE_NAME)
+    }
+
+    pub fn intervals_file_path(&self) -> PathBuf {
+        self.data_dir.join(crate::storage::INTERVALS_FILE_NAME)
+    }
+
+    pub fn lock_file_path(&self) -> PathBuf {
+        self.data_dir.join(crate::instance_lock::LOCK_FILE_NAME)
+    }
+}
+
+impl ::std::default::Default for Options {
+    fn default() -> Self { Self::new() }
+}
+
+fn project_dirs() -> ProjectDirs {
+    ProjectDirs::from("com",
+                      "liothique.xyz",
+                      "timeracker_core")
+        .expect("Cannot generate configuration storage directory path")
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content).map_err(|e| e.to_string()),
+        // If there is no file, no problem, everything is defaulted
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ConfigFile::default()),
+        Err(e) => Err(e.to_string()),
+    }
+}
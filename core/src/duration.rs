@@ -0,0 +1,103 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+
+// Rendering of tracked durations (in seconds) for humans and timesheets
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationStyle {
+    // 1h 23m, 4m 05s, 12s
+    #[default]
+    Short,
+    // 01:23:45
+    Clock,
+    // 1.39h, for timesheets
+    Decimal,
+    // 2d 3h 15m, for long projects, days being 24 hours
+    Days,
+    // 5025s, for scripts
+    Seconds,
+}
+
+pub const DURATION_STYLES: &[&str] = &["short", "clock", "decimal", "days", "seconds"];
+
+impl FromStr for DurationStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "short" => Ok(DurationStyle::Short),
+            "clock" => Ok(DurationStyle::Clock),
+            "decimal" => Ok(DurationStyle::Decimal),
+            "days" => Ok(DurationStyle::Days),
+            "seconds" => Ok(DurationStyle::Seconds),
+            other => Err(format!("unknown duration style: {} (expected one of {})", other, DURATION_STYLES.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for DurationStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DurationStyle::Short => "short",
+            DurationStyle::Clock => "clock",
+            DurationStyle::Decimal => "decimal",
+            DurationStyle::Days => "days",
+            DurationStyle::Seconds => "seconds",
+        };
+        f.write_str(name)
+    }
+}
+
+pub fn format_duration(secs: u64, style: DurationStyle) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    match style {
+        DurationStyle::Short if hours > 0 => format!("{}h {:02}m", hours, minutes),
+        DurationStyle::Short if minutes > 0 => format!("{}m {:02}s", minutes, seconds),
+        DurationStyle::Short => format!("{}s", seconds),
+        DurationStyle::Clock => format!("{:02}:{:02}:{:02}", hours, minutes, seconds),
+        DurationStyle::Decimal => format!("{:.2}h", secs as f64 / 3600.0),
+        DurationStyle::Days if hours >= 24 => format!("{}d {}h {:02}m", hours / 24, hours % 24, minutes),
+        DurationStyle::Days => format_duration(secs, DurationStyle::Short),
+        DurationStyle::Seconds => format!("{}s", secs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each style, in the order of DURATION_STYLES
+    fn all_styles(secs: u64) -> Vec<String> {
+        DURATION_STYLES.iter().map(|style| format_duration(secs, style.parse().unwrap())).collect()
+    }
+
+    #[test]
+    fn formats_every_style_at_the_boundaries() {
+        assert_eq!(all_styles(0), ["0s", "00:00:00", "0.00h", "0s", "0s"]);
+        assert_eq!(all_styles(59), ["59s", "00:00:59", "0.02h", "59s", "59s"]);
+        assert_eq!(all_styles(60), ["1m 00s", "00:01:00", "0.02h", "1m 00s", "60s"]);
+        assert_eq!(all_styles(3599), ["59m 59s", "00:59:59", "1.00h", "59m 59s", "3599s"]);
+        assert_eq!(all_styles(3600), ["1h 00m", "01:00:00", "1.00h", "1h 00m", "3600s"]);
+        assert_eq!(all_styles(86400), ["24h 00m", "24:00:00", "24.00h", "1d 0h 00m", "86400s"]);
+        assert_eq!(all_styles(90061), ["25h 01m", "25:01:01", "25.02h", "1d 1h 01m", "90061s"]);
+    }
+
+    #[test]
+    fn rounds_decimal_hours_to_the_nearest_hundredth() {
+        assert_eq!(format_duration(17, DurationStyle::Decimal), "0.00h");
+        assert_eq!(format_duration(19, DurationStyle::Decimal), "0.01h");
+        assert_eq!(format_duration(1799, DurationStyle::Decimal), "0.50h");
+        assert_eq!(format_duration(5025, DurationStyle::Decimal), "1.40h");
+    }
+
+    #[test]
+    fn parses_the_names_it_displays() {
+        for name in DURATION_STYLES {
+            assert_eq!(name.parse::<DurationStyle>().unwrap().to_string(), *name);
+        }
+        assert_eq!(" Clock ".parse::<DurationStyle>(), Ok(DurationStyle::Clock));
+        assert!("hours".parse::<DurationStyle>().is_err());
+    }
+}
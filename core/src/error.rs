@@ -0,0 +1,170 @@
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+// Broad category of an error, for clients to branch on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    // The request could not be understood
+    Parse,
+    // The request refers to something that does not exist
+    NotFound,
+    // The request contradicts the current state
+    Conflict,
+    // The client is not allowed to make this request
+    Forbidden,
+    // The resource exists, but not with this HTTP method
+    MethodNotAllowed,
+    // The request did not arrive in time
+    Timeout,
+    // The request exceeds a size limit
+    TooLarge,
+    // The request is valid but not handled by this core
+    Unsupported,
+    // Something went wrong on the core side
+    #[default]
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn http_status(self) -> u16 {
+        match self {
+            ErrorKind::Parse => 400,
+            ErrorKind::Forbidden => 403,
+            ErrorKind::NotFound => 404,
+            ErrorKind::MethodNotAllowed => 405,
+            ErrorKind::Timeout => 408,
+            ErrorKind::Conflict => 409,
+            ErrorKind::TooLarge => 413,
+            ErrorKind::Unsupported => 501,
+            ErrorKind::Internal => 500,
+        }
+    }
+}
+
+// Every error the core can report. Each variant has a stable numeric code,
+// sent as `error_code`; codes are never reused once published.
+// Codes 6000 to 6999 are raised by the HTTP front-end before a request reaches the state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProtocolError {
+    EmptyRequest,
+    UnknownCommand { command: String },
+    // More arguments than the command takes
+    UnexpectedArgument { command: &'static str, expected: usize },
+    MissingArgument { command: &'static str, field: &'static str },
+    InvalidArgument { command: &'static str, field: &'static str, expected: &'static str },
+    TopicNotFound { id: u64 },
+    // The parent_id argument of CREATE_TOPIC or UPDATE_TOPIC
+    ParentNotFound { parent_id: u64 },
+    Conflict { msg: String },
+    AuthenticationRequired,
+    InvalidToken,
+    // Valid, but refused on this kind of connection
+    NotAllowed { command: &'static str },
+    Unsupported { command: &'static str },
+    // Malformed HTTP request, or invalid path or query parameter
+    BadRequest { msg: String },
+    // Refused by the HTTP front-end, such as a request from a disallowed origin
+    Forbidden { msg: String },
+    UnknownRoute { path: String },
+    MethodNotAllowed { method: String },
+    RequestTimeout,
+    PayloadTooLarge,
+    HeadersTooLarge,
+    Internal { msg: String },
+}
+
+impl ProtocolError {
+    pub fn code(&self) -> u64 {
+        match self {
+            ProtocolError::EmptyRequest => 1000,
+            ProtocolError::UnknownCommand { .. } => 1001,
+            ProtocolError::UnexpectedArgument { .. } => 1002,
+            ProtocolError::MissingArgument { .. } => 1003,
+            ProtocolError::InvalidArgument { .. } => 1004,
+            ProtocolError::TopicNotFound { .. } => 2000,
+            ProtocolError::ParentNotFound { .. } => 2001,
+            ProtocolError::Conflict { .. } => 3000,
+            ProtocolError::AuthenticationRequired => 4000,
+            ProtocolError::InvalidToken => 4001,
+            ProtocolError::NotAllowed { .. } => 4002,
+            ProtocolError::Unsupported { .. } => 5000,
+            ProtocolError::BadRequest { .. } => 6000,
+            ProtocolError::Forbidden { .. } => 6001,
+            ProtocolError::UnknownRoute { .. } => 6002,
+            ProtocolError::MethodNotAllowed { .. } => 6003,
+            ProtocolError::RequestTimeout => 6004,
+            ProtocolError::PayloadTooLarge => 6005,
+            ProtocolError::HeadersTooLarge => 6006,
+            ProtocolError::Internal { .. } => 9000,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ProtocolError::EmptyRequest
+            | ProtocolError::UnknownCommand { .. }
+            | ProtocolError::UnexpectedArgument { .. }
+            | ProtocolError::MissingArgument { .. }
+            | ProtocolError::InvalidArgument { .. }
+            | ProtocolError::BadRequest { .. } => ErrorKind::Parse,
+            ProtocolError::TopicNotFound { .. }
+            | ProtocolError::ParentNotFound { .. }
+            | ProtocolError::UnknownRoute { .. } => ErrorKind::NotFound,
+            ProtocolError::Conflict { .. } => ErrorKind::Conflict,
+            ProtocolError::AuthenticationRequired
+            | ProtocolError::InvalidToken
+            | ProtocolError::NotAllowed { .. }
+            | ProtocolError::Forbidden { .. } => ErrorKind::Forbidden,
+            ProtocolError::MethodNotAllowed { .. } => ErrorKind::MethodNotAllowed,
+            ProtocolError::RequestTimeout => ErrorKind::Timeout,
+            ProtocolError::PayloadTooLarge
+            | ProtocolError::HeadersTooLarge => ErrorKind::TooLarge,
+            ProtocolError::Unsupported { .. } => ErrorKind::Unsupported,
+            ProtocolError::Internal { .. } => ErrorKind::Internal,
+        }
+    }
+
+    // Name of the offending request argument, when there is one
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            ProtocolError::MissingArgument { field, .. }
+            | ProtocolError::InvalidArgument { field, .. } => Some(field),
+            ProtocolError::TopicNotFound { .. } => Some("id"),
+            ProtocolError::ParentNotFound { .. } => Some("parent_id"),
+            ProtocolError::InvalidToken => Some("token"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::EmptyRequest => write!(f, "empty input"),
+            ProtocolError::UnknownCommand { command } => write!(f, "unknown command: {}", command),
+            ProtocolError::UnexpectedArgument { command, expected: 0 } => write!(f, "{} does not take arguments", command),
+            ProtocolError::UnexpectedArgument { command, expected: 1 } => write!(f, "{} takes exactly one argument", command),
+            ProtocolError::UnexpectedArgument { command, expected } => write!(f, "{} takes exactly {} arguments", command, expected),
+            ProtocolError::MissingArgument { command, field } => write!(f, "{} is missing its {} argument", command, field),
+            ProtocolError::InvalidArgument { command, field, expected } => write!(f, "{} {} must be {}", command, field, expected),
+            ProtocolError::TopicNotFound { id } => write!(f, "Topic not found: {}", id),
+            ProtocolError::ParentNotFound { parent_id } => write!(f, "Parent topic not found: {}", parent_id),
+            ProtocolError::Conflict { msg } => write!(f, "{}", msg),
+            ProtocolError::AuthenticationRequired => write!(f, "Authentication required"),
+            ProtocolError::InvalidToken => write!(f, "Invalid token"),
+            ProtocolError::NotAllowed { command } => write!(f, "{} is not allowed on this connection", command),
+            ProtocolError::Unsupported { command } => write!(f, "{} is not supported by this core", command),
+            ProtocolError::BadRequest { msg } => write!(f, "{}", msg),
+            ProtocolError::Forbidden { msg } => write!(f, "{}", msg),
+            ProtocolError::UnknownRoute { path } => write!(f, "No such resource: {}", path),
+            ProtocolError::MethodNotAllowed { method } => write!(f, "Method {} not allowed on this resource", method),
+            ProtocolError::RequestTimeout => write!(f, "Request not received in time"),
+            ProtocolError::PayloadTooLarge => write!(f, "Request body too large"),
+            ProtocolError::HeadersTooLarge => write!(f, "Request headers too large"),
+            ProtocolError::Internal { msg } => write!(f, "Server error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
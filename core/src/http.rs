@@ -0,0 +1,409 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+use tracing::{debug, error, info_span, warn, Instrument};
+
+use timeracker_common::{build_report, ClientRequest, Grouping, Period, ProtocolError, ReportOptions, ResponseToClient,
+                         TimeTrackingState, TimeTrackingTopic};
+use crate::{handle_request, ConnectionContext, NEXT_CONNECTION_ID};
+
+// Minimal HTTP/1.1 front-end: one request per connection, JSON bodies.
+// Every route is translated into a ClientRequest and goes through handle_request.
+// Browsers are only let in from the configured origins, and switching topics needs the auth token,
+// so that a web page cannot drive the core behind the user's back.
+
+pub const HTTP_MAX_HEADER_SIZE: usize = 16 * 1024;
+pub const HTTP_MAX_BODY_SIZE: usize = 64 * 1024;
+// For the whole request, so that a client sending nothing does not hold a task forever
+pub const HTTP_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    // What follows '?' in the target, empty if nothing does
+    query: String,
+    authorization: Option<String>,
+    // Set by browsers on cross-origin requests
+    origin: Option<String>,
+    body: Vec<u8>,
+}
+
+struct HttpResponse {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl HttpResponse {
+    fn json<T: Serialize>(status: u16, value: &T) -> HttpResponse {
+        HttpResponse { status, content_type: "application/json", body: serde_json::to_string(value).unwrap() }
+    }
+
+    // Errors raised by the HTTP layer itself, in the same format as those of the core
+    fn error(error: ProtocolError) -> HttpResponse {
+        HttpResponse::json(error.kind().http_status(), &ResponseToClient::from(error))
+    }
+
+    // Protocol errors keep their own code and kind, the HTTP status is derived from the kind
+    fn protocol_error(response: ResponseToClient) -> HttpResponse {
+        let status = match &response {
+            ResponseToClient::Error { kind, .. } => kind.http_status(),
+            _ => 500,
+        };
+        HttpResponse::json(status, &response)
+    }
+}
+
+// What to extract from the state for routes backed by GET_STATE
+enum StateView {
+    Full,
+    Topics,
+    Topic(u64),
+    Current,
+}
+
+enum Route {
+    FromState(StateView),
+    Request(ClientRequest),
+    // Built from GET_STATE and GET_INTERVALS
+    Report { period: Period, grouping: Grouping, since: Option<u64>, until: Option<u64> },
+    Metrics,
+}
+
+#[derive(Serialize)]
+struct TopicResource<'a> {
+    id: u64,
+    name: &'a str,
+    parent_id: u64,
+    duration: u64,
+}
+
+impl<'a> From<&'a TimeTrackingTopic> for TopicResource<'a> {
+    fn from(topic: &'a TimeTrackingTopic) -> Self {
+        TopicResource { id: topic.id, name: &topic.name, parent_id: topic.parent_id, duration: topic.duration }
+    }
+}
+
+#[derive(Serialize)]
+struct CurrentResource<'a> {
+    #[serde(flatten)]
+    topic: TopicResource<'a>,
+    since: u64,
+}
+
+#[derive(Deserialize)]
+struct SwitchBody {
+    id: u64,
+}
+
+pub async fn accept_http_connections(listener: TcpListener, context: Arc<ConnectionContext>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer_addr)) => {
+                let conn_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+                let span = info_span!("http", id = conn_id);
+                tokio::spawn(serve_http_connection(socket, peer_addr, context.clone()).instrument(span));
+            }
+            Err(e) => error!(error = ?e, "Error accepting HTTP socket"),
+        }
+    }
+}
+
+async fn serve_http_connection(mut socket: TcpStream, peer_addr: SocketAddr, context: Arc<ConnectionContext>) {
+    let mut allowed_origin = None;
+    let response = match time::timeout(HTTP_READ_TIMEOUT, read_request(&mut socket)).await {
+        Ok(Ok(request)) => {
+            debug!(%peer_addr, method = %request.method, path = %request.path, "HTTP request");
+            allowed_origin = request.origin.clone().filter(|origin| context.http_allowed_origins.contains(origin));
+            respond(request, &context).await
+        }
+        Ok(Err(response)) => response,
+        Err(_) => {
+            debug!(%peer_addr, "HTTP request not received in time");
+            HttpResponse::error(ProtocolError::RequestTimeout)
+        }
+    };
+
+    if let Err(e) = write_response(&mut socket, &response, allowed_origin.as_deref()).await {
+        error!(error = ?e, "Error on sending HTTP response");
+    }
+}
+
+async fn respond(request: HttpRequest, context: &ConnectionContext) -> HttpResponse {
+    if let Some(origin) = &request.origin {
+        if !context.http_allowed_origins.contains(origin) {
+            warn!(%origin, "Rejected HTTP request from a disallowed origin");
+            return HttpResponse::error(ProtocolError::Forbidden { msg: "Origin not allowed".to_string() });
+        }
+    }
+
+    // Answer CORS preflights so that dashboards served from an allowed origin can call the API
+    if request.method == "OPTIONS" {
+        return HttpResponse { status: 204, content_type: "application/json", body: String::new() };
+    }
+
+    let route = match route(&request) {
+        Ok(r) => r,
+        Err(response) => return response,
+    };
+
+    let mut authenticated = match (&context.auth_token, &request.authorization) {
+        (None, _) => true,
+        (Some(expected_token), Some(authorization)) => authorization.strip_prefix("Bearer ") == Some(expected_token.as_str()),
+        (Some(_), None) => false,
+    };
+
+    match route {
+        // Unlike reads, never allowed without a token
+        Route::Request(client_request @ ClientRequest::SwitchTopic { .. }) => {
+            if context.auth_token.is_none() {
+                return HttpResponse::error(ProtocolError::Forbidden {
+                    msg: "Switching topics over HTTP requires an auth token to be configured".to_string()
+                });
+            }
+            let response = handle_request(client_request, context, &mut authenticated).await;
+            match response {
+                error @ ResponseToClient::Error { .. } => HttpResponse::protocol_error(error),
+                other => HttpResponse::json(200, &other),
+            }
+        }
+        Route::Request(client_request) => {
+            let response = handle_request(client_request, context, &mut authenticated).await;
+            match response {
+                error @ ResponseToClient::Error { .. } => HttpResponse::protocol_error(error),
+                other => HttpResponse::json(200, &other),
+            }
+        }
+        // Scrapes are not counted as GET_STATE requests, they would drown the actual clients
+        Route::Metrics => {
+            if !authenticated {
+                return HttpResponse::error(ProtocolError::AuthenticationRequired);
+            }
+            match context.state.request(ClientRequest::GetState {}).await {
+                ResponseToClient::State { value } => match serde_json::from_str(&value) {
+                    Ok(state) => HttpResponse {
+                        status: 200,
+                        content_type: "text/plain; version=0.0.4",
+                        body: context.metrics.render(&state)
+                    },
+                    Err(e) => HttpResponse::error(ProtocolError::Internal { msg: format!("invalid state: {}", e) }),
+                },
+                _ => HttpResponse::error(ProtocolError::Internal { msg: "unexpected response to GET_STATE".to_string() }),
+            }
+        }
+        Route::Report { period, grouping, since, until } => {
+            let state = match handle_request(ClientRequest::GetState {}, context, &mut authenticated).await {
+                ResponseToClient::State { value } => match serde_json::from_str::<TimeTrackingState>(&value) {
+                    Ok(state) => state,
+                    Err(e) => return HttpResponse::error(ProtocolError::Internal { msg: format!("invalid state: {}", e) }),
+                },
+                error @ ResponseToClient::Error { .. } => return HttpResponse::protocol_error(error),
+                _ => return HttpResponse::error(ProtocolError::Internal { msg: "unexpected response to GET_STATE".to_string() }),
+            };
+            match handle_request(ClientRequest::GetIntervals { since, until }, context, &mut authenticated).await {
+                ResponseToClient::Intervals { intervals } => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+                    let options = ReportOptions { period, grouping, since, until, now };
+                    HttpResponse::json(200, &build_report(&state, &intervals, &options))
+                }
+                error @ ResponseToClient::Error { .. } => HttpResponse::protocol_error(error),
+                _ => HttpResponse::error(ProtocolError::Internal { msg: "unexpected response to GET_INTERVALS".to_string() }),
+            }
+        }
+        Route::FromState(view) => {
+            let response = handle_request(ClientRequest::GetState {}, context, &mut authenticated).await;
+            match response {
+                ResponseToClient::State { value } => match serde_json::from_str(&value) {
+                    Ok(state) => render_state(&state, view),
+                    Err(e) => HttpResponse::error(ProtocolError::Internal { msg: format!("invalid state: {}", e) }),
+                },
+                error @ ResponseToClient::Error { .. } => HttpResponse::protocol_error(error),
+                _ => HttpResponse::error(ProtocolError::Internal { msg: "unexpected response to GET_STATE".to_string() }),
+            }
+        }
+    }
+}
+
+fn route(request: &HttpRequest) -> Result<Route, HttpResponse> {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["api", "state"]) => Ok(Route::FromState(StateView::Full)),
+        ("GET", ["api", "topics"]) => Ok(Route::FromState(StateView::Topics)),
+        ("GET", ["api", "topics", id]) => {
+            let id = id.parse().map_err(|_| bad_request("Topic id must be an unsigned integer (u64)"))?;
+            Ok(Route::FromState(StateView::Topic(id)))
+        }
+        ("GET", ["api", "current"]) => Ok(Route::FromState(StateView::Current)),
+        ("PUT", ["api", "current"]) => {
+            let body: SwitchBody = serde_json::from_slice(&request.body)
+                .map_err(|e| bad_request(&format!("Expected {{\"id\": <topic id>}}: {}", e)))?;
+            Ok(Route::Request(ClientRequest::SwitchTopic { id: body.id }))
+        }
+        ("GET", ["api", "intervals"]) => Ok(Route::Request(ClientRequest::GetIntervals {
+            since: query_u64(request, "since")?,
+            until: query_u64(request, "until")?
+        })),
+        ("GET", ["api", "report"]) => {
+            let period = query_value(request, "by").unwrap_or("week").parse().map_err(|e: String| bad_request(&e))?;
+            let grouping = query_value(request, "group").unwrap_or("subtree").parse().map_err(|e: String| bad_request(&e))?;
+            Ok(Route::Report { period, grouping, since: query_u64(request, "since")?, until: query_u64(request, "until")? })
+        }
+        ("GET", ["metrics"]) => Ok(Route::Metrics),
+        (_, ["api", "state"]) | (_, ["api", "topics"]) | (_, ["api", "topics", _])
+        | (_, ["api", "current"]) | (_, ["api", "intervals"]) | (_, ["api", "report"]) | (_, ["metrics"]) => Err(HttpResponse::error(ProtocolError::MethodNotAllowed { method: request.method.clone() })),
+        _ => Err(HttpResponse::error(ProtocolError::UnknownRoute { path: request.path.clone() })),
+    }
+}
+
+// Value of `name` in the query string, which only ever carries numbers and keywords, so nothing is percent-decoded
+fn query_value<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    request.query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+fn query_u64(request: &HttpRequest, name: &str) -> Result<Option<u64>, HttpResponse> {
+    query_value(request, name).map(|value| value.parse())
+        .transpose()
+        .map_err(|_| bad_request(&format!("{} must be an unsigned integer (u64)", name)))
+}
+
+fn bad_request(msg: &str) -> HttpResponse {
+    HttpResponse::error(ProtocolError::BadRequest { msg: msg.to_string() })
+}
+
+fn render_state(state: &TimeTrackingState, view: StateView) -> HttpResponse {
+    let find_topic = |id: u64| state.topics_tree.iter().find(|topic| topic.id == id);
+
+    match view {
+        StateView::Full => HttpResponse::json(200, state),
+        StateView::Topics => {
+            let topics: Vec<TopicResource> = state.topics_tree.iter().map(TopicResource::from).collect();
+            HttpResponse::json(200, &topics)
+        }
+        StateView::Topic(id) => match find_topic(id) {
+            Some(topic) => HttpResponse::json(200, &TopicResource::from(topic)),
+            None => HttpResponse::error(ProtocolError::TopicNotFound { id }),
+        },
+        StateView::Current => match find_topic(state.current_topic_id) {
+            Some(topic) => HttpResponse::json(200, &CurrentResource {
+                topic: TopicResource::from(topic),
+                since: state.current_topic_since
+            }),
+            None => HttpResponse::error(ProtocolError::Internal { msg: "current topic not found".to_string() }),
+        },
+    }
+}
+
+async fn read_request(socket: &mut TcpStream) -> Result<HttpRequest, HttpResponse> {
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+
+    let header_end = loop {
+        if let Some(pos) = find_subsequence(&buffer, b"\r\n\r\n") {
+            break pos;
+        }
+        if buffer.len() > HTTP_MAX_HEADER_SIZE {
+            return Err(HttpResponse::error(ProtocolError::HeadersTooLarge));
+        }
+        let read = socket.read(&mut chunk).await.map_err(|_| bad_request("Connection error"))?;
+        if read == 0 {
+            return Err(bad_request("Incomplete request"));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+
+    let request_line = lines.next().unwrap_or_default();
+    let mut request_line_parts = request_line.split(' ');
+    let (method, target) = match (request_line_parts.next(), request_line_parts.next()) {
+        (Some(method), Some(target)) if !method.is_empty() => (method.to_string(), target),
+        _ => return Err(bad_request("Malformed request line")),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    let mut origin = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => {
+                    content_length = value.parse().map_err(|_| bad_request("Invalid Content-Length"))?;
+                }
+                "authorization" => authorization = Some(value.to_string()),
+                "origin" => origin = Some(value.to_string()),
+                _ => (),
+            }
+        }
+    }
+
+    if content_length > HTTP_MAX_BODY_SIZE {
+        return Err(HttpResponse::error(ProtocolError::PayloadTooLarge));
+    }
+
+    let mut body = buffer.split_off(header_end + 4);
+    while body.len() < content_length {
+        let read = socket.read(&mut chunk).await.map_err(|_| bad_request("Connection error"))?;
+        if read == 0 {
+            warn!("HTTP body shorter than its Content-Length");
+            return Err(bad_request("Incomplete request body"));
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+
+    Ok(HttpRequest { method, path, query, authorization, origin, body })
+}
+
+// CORS headers are only sent to an allowed origin
+async fn write_response(socket: &mut TcpStream, response: &HttpResponse, allowed_origin: Option<&str>) -> std::io::Result<()> {
+    let cors = match allowed_origin {
+        Some(origin) => format!("Access-Control-Allow-Origin: {}\r\n\
+                                 Access-Control-Allow-Methods: GET, PUT, OPTIONS\r\n\
+                                 Access-Control-Allow-Headers: Authorization, Content-Type\r\n\
+                                 Vary: Origin\r\n", origin),
+        None => String::new(),
+    };
+    let head = format!("HTTP/1.1 {} {}\r\n\
+                        Content-Type: {}\r\n\
+                        Content-Length: {}\r\n\
+                        {}\
+                        Connection: close\r\n\r\n",
+                       response.status, reason_phrase(response.status), response.content_type, response.body.len(), cors);
+
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(response.body.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        501 => "Not Implemented",
+        _ => "Internal Server Error",
+    }
+}
+
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
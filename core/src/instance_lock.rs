@@ -0,0 +1,56 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use fs2::FileExt;
+
+pub const LOCK_FILE_NAME: &str = "timeracker_core.pid";
+
+// Exclusive lock on the pid file, held for the whole lifetime of the core.
+// The lock is released by the OS if the core crashes, so a stale file never blocks a restart.
+// The file itself is left in place: removing it would let a core starting meanwhile lock a file
+// that is about to be unlinked, and a third one create and lock a new file alongside it.
+pub struct InstanceLock {
+    file: File,
+}
+
+pub enum InstanceLockError {
+    // Another core holds the lock, with the pid it wrote if readable
+    AlreadyRunning(Option<u32>),
+    Io(io::Error),
+}
+
+impl InstanceLock {
+    pub fn acquire(path: &Path) -> Result<InstanceLock, InstanceLockError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(InstanceLockError::Io)?;
+        }
+
+        // Not truncated before locking, the running core's pid is read back on failure
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+            .map_err(InstanceLockError::Io)?;
+
+        if file.try_lock_exclusive().is_err() {
+            let mut content = String::new();
+            let pid = file.read_to_string(&mut content).ok().and_then(|_| content.trim().parse().ok());
+            return Err(InstanceLockError::AlreadyRunning(pid));
+        }
+
+        let write_pid = |file: &mut File| -> io::Result<()> {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            writeln!(file, "{}", std::process::id())?;
+            file.sync_all()
+        };
+        write_pid(&mut file).map_err(InstanceLockError::Io)?;
+
+        Ok(InstanceLock { file })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Emptied while still locked, so that no stale pid is reported
+        let _ = self.file.set_len(0);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
@@ -2,14 +2,41 @@ use serde::{Serialize, Deserialize};
 use std::sync::{Arc, Weak};
 use std::time::Instant;
 
+mod blocking_client;
+mod client;
+mod duration;
+mod error;
+mod report;
+#[cfg(any(test, feature = "test-util"))]
+mod test_server;
+mod tracker;
 
-pub const CLIENTREQUEST_MAX_PARTS: usize = 4;
+pub use blocking_client::BlockingTimeRackerClient;
+pub use client::{ClientError, ClientOptions, ServerError, TimeRackerClient, DEFAULT_SERVER};
+pub use duration::{format_duration, DurationStyle, DURATION_STYLES};
+pub use error::{ErrorKind, ProtocolError};
+pub use report::{ancestors, build_report, local_date, local_timestamp, start_of_day, tags, topic_path, Grouping, Period, Report, ReportOptions, ReportPeriod, ReportRow, GROUPINGS, PERIODS, UNTAGGED};
+#[cfg(any(test, feature = "test-util"))]
+pub use test_server::{Fault, TestServer};
+pub use tracker::{Clock, FakeClock, SystemClock, TimeTracker, TopicSwitch};
+
+
+// Command keyword plus the four arguments of UPDATE_TOPIC
+pub const CLIENTREQUEST_MAX_PARTS: usize = 5;
+#[derive(Clone)]
 pub enum ClientRequest {
     GetState {  },
     SwitchTopic { id: u64},
     CreateTopic {name: String, parent_id: u64},
     UpdateTopic {id: u64, name: String, parent_id: u64, duration: u64},
     DeleteTopic {id: u64},
+    // Intervals overlapping [since, until), either bound being optional
+    GetIntervals {since: Option<u64>, until: Option<u64>},
+    // Note of the running interval, None to clear it
+    SetNote {note: Option<String>},
+    // The user is active, see TimeTracker::check_idle
+    Activity {},
+    Auth {token: String},
     Bye {},
     Terminate {}
 }
@@ -23,24 +50,103 @@ pub enum ResponseToClient {
     Success {
         details: String
     },
+    // See ProtocolError for the meaning of each error_code
     Error {
         error_code: u64,
+        #[serde(default)]
+        kind: ErrorKind,
         msg: String,
+        // Offending request argument, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        field: Option<String>,
+    },
+    Intervals {
+        intervals: Vec<TimeTrackingInterval>
+    },
+    // Pushed unprompted to streaming clients (WebSocket) whenever the state changes
+    Event {
+        event: StateChangeEvent
     },
     Bye {},
     Terminating {}
 }
 
+impl From<ProtocolError> for ResponseToClient {
+    fn from(error: ProtocolError) -> Self {
+        ResponseToClient::Error {
+            error_code: error.code(),
+            kind: error.kind(),
+            msg: error.to_string(),
+            field: error.field().map(str::to_string),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind")]
+pub enum StateChangeEvent {
+    TopicSwitched {
+        previous_topic_id: u64,
+        topic_id: u64,
+        // Seconds since the Unix epoch
+        since: u64,
+    },
+    TopicCreated {
+        topic_id: u64,
+        name: String,
+        parent_id: u64,
+    },
+    TopicUpdated {
+        topic_id: u64,
+        name: String,
+        parent_id: u64,
+        duration: u64,
+    },
+    NoteSet {
+        topic_id: u64,
+        note: Option<String>,
+    },
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub struct TimeTrackingTopic {
     pub id: u64,
     pub name: String,
     pub duration: u64,
+    // Id of the parent topic, 0 for top-level topics
+    #[serde(default)]
+    pub parent_id: u64,
     pub parent: Weak<TimeTrackingTopic>,
     pub dependants: Vec<Arc<TimeTrackingTopic>>,
 }
 
+impl TimeTrackingTopic {
+    pub fn new(id: u64, name: &str, parent_id: u64) -> TimeTrackingTopic {
+        TimeTrackingTopic {
+            id,
+            name: name.to_string(),
+            duration: 0,
+            parent_id,
+            parent: Weak::new(), // empty
+            dependants: vec![],
+        }
+    }
+}
+
+// A period of time spent on a topic. Times are seconds since the Unix epoch.
+// Time spent on OFF is not recorded.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TimeTrackingInterval {
+    pub topic_id: u64,
+    pub start: u64,
+    // None for the interval still running on the current topic
+    pub end: Option<u64>,
+    // Free text set with SET_NOTE while the interval was running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
 pub struct TimeTrackingImplDetails {
     pub current_topic_start_instant: Instant
 }
@@ -62,6 +168,9 @@ impl ::std::default::Default for TimeTrackingImplDetails {
 pub struct TimeTrackingState {
     pub last_assigned_topic_id: u64,
     pub current_topic_id: u64,
+    // When the current topic was switched to, in seconds since the Unix epoch
+    #[serde(default)]
+    pub current_topic_since: u64,
     pub topics_tree: Vec<TimeTrackingTopic>,
 
     #[serde(skip)]
@@ -73,127 +182,169 @@ pub struct TimeTrackingState {
 
 
 impl ClientRequest {
+    // Protocol keyword of the request, also used to label it in logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientRequest::GetState{} => "GET_STATE",
+            ClientRequest::SwitchTopic{..} => "SWITCH_TOPIC",
+            ClientRequest::CreateTopic{..} => "CREATE_TOPIC",
+            ClientRequest::UpdateTopic{..} => "UPDATE_TOPIC",
+            ClientRequest::DeleteTopic{..} => "DELETE_TOPIC",
+            ClientRequest::GetIntervals{..} => "GET_INTERVALS",
+            ClientRequest::SetNote{..} => "SET_NOTE",
+            ClientRequest::Activity{} => "ACTIVITY",
+            ClientRequest::Auth{..} => "AUTH",
+            ClientRequest::Bye{} => "BYE",
+            ClientRequest::Terminate{} => "TERMINATE",
+        }
+    }
+
+    // Arguments are separated by spaces and requests by line breaks, so names and tokens must be
+    // single words and notes single lines. Clients check this before sending, see emit.
+    pub fn validate(&self) -> Result<(), ProtocolError> {
+        let word = |command, field, value: &str| {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                Err(ProtocolError::InvalidArgument { command, field, expected: "a single word, without spaces or line breaks" })
+            } else {
+                Ok(())
+            }
+        };
+        match self {
+            ClientRequest::CreateTopic{name, ..} => word("CREATE_TOPIC", "name", name),
+            ClientRequest::UpdateTopic{name, ..} => word("UPDATE_TOPIC", "name", name),
+            ClientRequest::Auth{token} => word("AUTH", "token", token),
+            ClientRequest::SetNote{note: Some(note)} if note.contains(['\r', '\n']) => {
+                Err(ProtocolError::InvalidArgument { command: "SET_NOTE", field: "note", expected: "a single line" })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Requests made on behalf of a user at work, which put off the fall back to Idle.
+    // Reads are not, status bars poll them whether anyone is there or not.
+    pub fn is_activity(&self) -> bool {
+        matches!(self, ClientRequest::SwitchTopic{..} | ClientRequest::CreateTopic{..} | ClientRequest::UpdateTopic{..}
+            | ClientRequest::DeleteTopic{..} | ClientRequest::SetNote{..} | ClientRequest::Activity{})
+    }
+
+    // Only meaningful for requests that pass validate, the line would not parse back otherwise
     pub fn emit(&self) -> String {
         match self {
             ClientRequest::GetState{} => {"GET_STATE".to_string()},
             ClientRequest::SwitchTopic{id} => {format!("SWITCH_TOPIC {}", id)},
             ClientRequest::CreateTopic{name, parent_id} => {format!("CREATE_TOPIC {} {}", name, parent_id)},
             ClientRequest::UpdateTopic{id, name, parent_id, duration} => {format!("UPDATE_TOPIC {} {} {} {}", id, name, parent_id, duration)},
-            ClientRequest::DeleteTopic{id} => {format!("DELETE_TOPIC {} ", id)},
+            ClientRequest::DeleteTopic{id} => {format!("DELETE_TOPIC {}", id)},
+            ClientRequest::GetIntervals{since: None, until: None} => {"GET_INTERVALS".to_string()},
+            ClientRequest::GetIntervals{since, until: None} => {format!("GET_INTERVALS {}", since.unwrap_or(0))},
+            ClientRequest::GetIntervals{since, until: Some(until)} => {format!("GET_INTERVALS {} {}", since.unwrap_or(0), until)},
+            ClientRequest::SetNote{note: None} => {"SET_NOTE".to_string()},
+            ClientRequest::SetNote{note: Some(note)} => {format!("SET_NOTE {}", note)},
+            ClientRequest::Activity{} => {"ACTIVITY".to_string()},
+            ClientRequest::Auth{token} => {format!("AUTH {}", token)},
             ClientRequest::Bye{} => {"BYE".to_string()},
             ClientRequest::Terminate{} => {"TERMINATE".to_string()},
         }
     }
 
-    pub fn parse(input: &str) -> Result<ClientRequest, String> {
+    pub fn parse(input: &str) -> Result<ClientRequest, ProtocolError> {
         let mut parts = input.splitn(CLIENTREQUEST_MAX_PARTS, ' ');
-        match parts.next() {
-            Some("GET_STATE") => {
-                if parts.next().is_some() {
-                    return Err("GET_STATE does not take arguments".into());
-                }
+        let command = match parts.next() {
+            Some("") | None => return Err(ProtocolError::EmptyRequest),
+            Some(cmd) => cmd,
+        };
+        let args: Vec<&str> = parts.collect();
+
+        match command {
+            "GET_STATE" => {
+                expect_arguments("GET_STATE", &args, &[])?;
                 Ok(ClientRequest::GetState { })
             }
 
-            Some("BYE") => {
-                if parts.next().is_some() {
-                    return Err("BYE does not take arguments".into());
-                }
+            "GET_INTERVALS" => {
+                // GET_INTERVALS [since [until]]
+                let fields = ["since", "until"];
+                expect_arguments("GET_INTERVALS", &args, &fields[..args.len().min(fields.len())])?;
+                Ok(ClientRequest::GetIntervals {
+                    since: args.first().map(|since| parse_u64_argument("GET_INTERVALS", "since", since)).transpose()?,
+                    until: args.get(1).map(|until| parse_u64_argument("GET_INTERVALS", "until", until)).transpose()?
+                })
+            }
+
+            "SET_NOTE" => {
+                // The note is the rest of the line, spaces included
+                let note = args.join(" ").trim().to_string();
+                Ok(ClientRequest::SetNote { note: Some(note).filter(|note| !note.is_empty()) })
+            }
+
+            "ACTIVITY" => {
+                expect_arguments("ACTIVITY", &args, &[])?;
+                Ok(ClientRequest::Activity { })
+            }
+
+            "AUTH" => {
+                expect_arguments("AUTH", &args, &["token"])?;
+                Ok(ClientRequest::Auth { token: args[0].to_string() })
+            }
+
+            "BYE" => {
+                expect_arguments("BYE", &args, &[])?;
                 Ok(ClientRequest::Bye { })
             }
 
-            Some("TERMINATE") => {
-                if parts.next().is_some() {
-                    return Err("BYE does not take arguments".into());
-                }
+            "TERMINATE" => {
+                expect_arguments("TERMINATE", &args, &[])?;
                 Ok(ClientRequest::Terminate { })
             }
 
-            Some("SWITCH_TOPIC") => {
-                let id_str = parts.next().ok_or("SWITCH_TOPIC must be followed by an id")?;
-                if parts.next().is_some() {
-                    return Err("SWITCH_TOPIC takes exactly one argument".into());
-                }
-                let id = id_str.parse();
-                if id.is_err() {
-                    return Err("SWITCH_TOPIC argument must be an unsigned integer (u64)".into());
-                }
-                let id= id.unwrap();
-                Ok(ClientRequest::SwitchTopic { id })
+            "SWITCH_TOPIC" => {
+                expect_arguments("SWITCH_TOPIC", &args, &["id"])?;
+                Ok(ClientRequest::SwitchTopic {
+                    id: parse_u64_argument("SWITCH_TOPIC", "id", args[0])?
+                })
             }
 
-            Some("CREATE_TOPIC") => {
-                let name_str = parts.next();
-                let id_str = parts.next();
-                if parts.next().is_some() || id_str.is_none() || name_str.is_none() {
-                    return Err("CREATE_TOPIC must be followed by exactly two arguments (name and parent id)".into());
-                }
-                let id_str = id_str.unwrap();
-                let id = id_str.parse();
-                if id.is_err() {
-                    return Err("CREATE_TOPIC second argument must be an unsigned integer (u64)".into());
-                }
-                let id= id.unwrap();
+            "CREATE_TOPIC" => {
+                expect_arguments("CREATE_TOPIC", &args, &["name", "parent_id"])?;
                 Ok(ClientRequest::CreateTopic {
-                    name: name_str.unwrap().to_string(), parent_id: id
+                    name: args[0].to_string(),
+                    parent_id: parse_u64_argument("CREATE_TOPIC", "parent_id", args[1])?
                 })
             }
 
-            Some("UPDATE_TOPIC") => {
-                let id_str = parts.next();
-                let name_str = parts.next();
-                let parent_id_str = parts.next();
-                let duration_str = parts.next();
-                if parts.next().is_some() || name_str.is_none() || parent_id_str.is_none() || duration_str.is_none() {
-                    return Err("UPDATE_TOPIC must be followed by exactly four arguments (id, name, parent id, duration)".into());
-                }
-
-                let name_str = name_str.unwrap();
-                let id_str = id_str.unwrap();
-                let parent_id_str = parent_id_str.unwrap();
-                let duration_str = duration_str.unwrap();
-
-                let id = id_str.parse();
-                if id.is_err() {
-                    return Err("UPDATE_TOPIC first argument must be an unsigned integer (u64)".into());
-                }
-                let id= id.unwrap();
-
-                let parent_id = parent_id_str.parse();
-                if parent_id.is_err() {
-                    return Err("UPDATE_TOPIC third argument must be an unsigned integer (u64)".into());
-                }
-                let parent_id= parent_id.unwrap();
-
-                let duration = duration_str.parse();
-                if duration.is_err() {
-                    return Err("UPDATE_TOPIC fourth argument must be an unsigned integer (u64)".into());
-                }
-                let duration= duration.unwrap();
-
-
+            "UPDATE_TOPIC" => {
+                expect_arguments("UPDATE_TOPIC", &args, &["id", "name", "parent_id", "duration"])?;
                 Ok(ClientRequest::UpdateTopic {
-                    id, name: name_str.to_string(), parent_id, duration
+                    id: parse_u64_argument("UPDATE_TOPIC", "id", args[0])?,
+                    name: args[1].to_string(),
+                    parent_id: parse_u64_argument("UPDATE_TOPIC", "parent_id", args[2])?,
+                    duration: parse_u64_argument("UPDATE_TOPIC", "duration", args[3])?
                 })
             }
 
-            Some("DELETE_TOPIC") => {
-                let id_str = parts.next().ok_or("DELETE_TOPIC must be followed by an id")?;
-                if parts.next().is_some() {
-                    return Err("DELETE_TOPIC takes exactly one argument".into());
-                }
-
-                let id = id_str.parse();
-                if id.is_err() {
-                    return Err("DELETE_TOPIC argument must be an unsigned integer (u64)".into());
-                }
-                let id= id.unwrap();
-
-                Ok(ClientRequest::DeleteTopic { id })
+            "DELETE_TOPIC" => {
+                expect_arguments("DELETE_TOPIC", &args, &["id"])?;
+                Ok(ClientRequest::DeleteTopic {
+                    id: parse_u64_argument("DELETE_TOPIC", "id", args[0])?
+                })
             }
 
-            Some(cmd) => Err(format!("unknown command: {}", cmd)),
-            None => Err("empty input".into()),
+            cmd => Err(ProtocolError::UnknownCommand { command: cmd.to_string() }),
         }
     }
 }
+
+// Checks that exactly one argument was given for each expected field
+fn expect_arguments(command: &'static str, args: &[&str], fields: &[&'static str]) -> Result<(), ProtocolError> {
+    if args.len() > fields.len() {
+        return Err(ProtocolError::UnexpectedArgument { command, expected: fields.len() });
+    }
+    match fields.get(args.len()) {
+        Some(field) => Err(ProtocolError::MissingArgument { command, field }),
+        None => Ok(()),
+    }
+}
+
+fn parse_u64_argument(command: &'static str, field: &'static str, value: &str) -> Result<u64, ProtocolError> {
+    value.parse().map_err(|_| ProtocolError::InvalidArgument { command, field, expected: "an unsigned integer (u64)" })
+}
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+pub const LOG_FILE_PREFIX: &str = "timeracker_core.log";
+
+pub struct LoggingOptions {
+    // Either a bare level ("debug") or a full filter directive ("timeracker_core=trace")
+    pub level: String,
+    // When set, logs are also written to a daily-rotated file in this directory
+    pub log_dir: Option<PathBuf>,
+}
+
+impl LoggingOptions {
+    pub fn new() -> LoggingOptions {
+        LoggingOptions {
+            level: DEFAULT_LOG_LEVEL.to_string(),
+            log_dir: None
+        }
+    }
+}
+
+impl ::std::default::Default for LoggingOptions {
+    fn default() -> Self { Self::new() }
+}
+
+// Installs the global subscriber. The returned guard flushes the log file on drop,
+// so it must be kept alive for as long as the core runs.
+pub fn init_logging(options: &LoggingOptions) -> Option<WorkerGuard> {
+    let filter = EnvFilter::try_new(&options.level).unwrap_or_else(|e| {
+        eprintln!("Invalid log level \"{}\" ({}), using \"{}\"", options.level, e, DEFAULT_LOG_LEVEL);
+        EnvFilter::new(DEFAULT_LOG_LEVEL)
+    });
+
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false);
+
+    match &options.log_dir {
+        Some(log_dir) => {
+            let file_appender = tracing_appender::rolling::daily(log_dir, LOG_FILE_PREFIX);
+            let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+            subscriber.with_ansi(false).with_writer(std::io::stdout.and(file_writer)).init();
+            Some(guard)
+        }
+        None => {
+            subscriber.init();
+            None
+        }
+    }
+}
@@ -1,210 +1,307 @@
-use std::env;
-use std::sync::{Arc, Mutex, Weak, MutexGuard};
-use tokio::net::TcpListener;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
 use tokio::stream::StreamExt;
 use tokio_util::codec::{Framed, LinesCodec};
 use futures::SinkExt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+mod actor;
+mod config;
+mod http;
+mod instance_lock;
+mod logging;
+mod metrics;
+mod storage;
+mod systemd;
+mod websocket;
+
+use timeracker_common::{ClientRequest, ProtocolError, ResponseToClient, TimeTracker};
+use actor::{spawn_state_actor, StateActorOptions, StateHandle};
+use config::Options;
+use instance_lock::{InstanceLock, InstanceLockError};
+use logging::init_logging;
+use metrics::Metrics;
+
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+// Per-connection settings shared by every connection task
+struct ConnectionContext {
+    state: StateHandle,
+    terminate_sender: mpsc::Sender<()>,
+    auth_token: Option<String>,
+    http_allowed_origins: Vec<String>,
+    ws_allowed_origins: Vec<String>,
+    metrics: Metrics,
+}
 
+fn main(){
+    let options = Options::load();
+    // Read and cleared before the runtime starts its worker threads
+    let activated_listeners = systemd::take_listeners();
 
-
-
-use timeracker_common::{ClientRequest, ResponseToClient, TimeTrackingTopic, TimeTrackingState, TimeTrackingImplDetails};
-use std::time::Instant;
-
-
-fn terminate_server() {
-    println!("    TimeRacker core exiting...");
-    std::process::exit(0);
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Cannot start the async runtime");
+    runtime.block_on(run(options, activated_listeners));
 }
 
-fn find_topic(id: u64, topics_tree: &Vec<TimeTrackingTopic>) -> Option<&TimeTrackingTopic> {
-    for ttt in topics_tree.iter() {
-        if ttt.id == id {
-            return Some(ttt);
+async fn run(options: Options, activated_listeners: io::Result<Vec<std::net::TcpListener>>) {
+    // Keep the guard alive until exit so that buffered log lines reach the log file
+    let _log_guard = init_logging(&options.logging_options());
+
+    info!("TimeRacker core starting...");
+    debug!(listen = ?options.listen, data_dir = %options.data_dir.display(), auth = options.auth_token.is_some(), "Effective configuration");
+
+    // Held until exit: a second core must not track a diverging state on the same data
+    let lock_file_path = options.lock_file_path();
+    let _instance_lock = match InstanceLock::acquire(&lock_file_path) {
+        Ok(lock) => lock,
+        Err(InstanceLockError::AlreadyRunning(pid)) => {
+            let pid = pid.map(|pid| pid.to_string()).unwrap_or_else(|| "unknown".to_string());
+            error!(pid = %pid, path = %lock_file_path.display(), "Another TimeRacker core is already running");
+            std::process::exit(1);
         }
-    }
-    return None
-}
-
-fn find_topic_mutable(id: u64, topics_tree: &mut Vec<TimeTrackingTopic>) -> Option<&mut TimeTrackingTopic> {
-    for ttt in topics_tree.iter_mut() {
-        if ttt.id == id {
-            return Some(ttt);
+        Err(InstanceLockError::Io(e)) => {
+            error!(error = %e, path = %lock_file_path.display(), "Could not create the lock file");
+            std::process::exit(1);
         }
-    }
-    return None
-}
-
-fn update_duration(state: &mut MutexGuard<TimeTrackingState>) {
-    let curr_duration = state.details.current_topic_start_instant.elapsed();
-    let curr_topic = find_topic_mutable(state.current_topic_id, &mut state.topics_tree).unwrap();
-    curr_topic.duration += curr_duration.as_secs();
-}
-
-fn reset_current_topic_start_instant(state: &mut MutexGuard<TimeTrackingState>) {
-    state.details.current_topic_start_instant = Instant::now();
-}
-
-#[tokio::main]
-async fn main(){
-    println!("    TimeRacker core starting...");
+    };
 
-    let off_topic= TimeTrackingTopic {
-        id: 0,
-        name: "OFF".to_string(),
-        duration: 0,
-        parent: Weak::new(), // empty
-        dependants: vec![],
+    let state_file_path = options.state_file_path();
+    let initial_state = match storage::load_state(&state_file_path) {
+        Ok(Some(state)) => {
+            info!(path = %state_file_path.display(), "Loaded saved state");
+            state
+        }
+        Ok(None) => {
+            info!(path = %state_file_path.display(), "No saved state, starting from the default topics");
+            storage::initial_state(&options.default_topics)
+        }
+        Err(e) => {
+            error!(error = %e, path = %state_file_path.display(), "Could not load saved state");
+            std::process::exit(1);
+        }
     };
 
-    let idle_topic=  TimeTrackingTopic {
-        id: 1,
-        name: "Idle".to_string(),
-        duration: 0,
-        parent: Weak::new(), // empty
-        dependants: vec![],
+    let intervals_file_path = options.intervals_file_path();
+    let intervals = match storage::load_intervals(&intervals_file_path) {
+        Ok(intervals) => intervals,
+        Err(e) => {
+            error!(error = %e, path = %intervals_file_path.display(), "Could not load saved intervals");
+            std::process::exit(1);
+        }
     };
 
-    let example_topic= TimeTrackingTopic {
-        id: 2,
-        name: "Work".to_string(),
-        duration: 0,
-        parent: Weak::new(), // empty
-        dependants: vec![],
+    // Time while the core was not running is not tracked
+    let tracker = TimeTracker::with_system_clock(initial_state, intervals);
+
+    let secs_to_threshold = |secs: u64| if secs > 0 { Some(Duration::from_secs(secs)) } else { None };
+    let state_handle = spawn_state_actor(tracker, StateActorOptions {
+        state_file_path,
+        intervals_file_path,
+        idle_after: secs_to_threshold(options.idle_after_secs),
+        off_after: secs_to_threshold(options.off_after_secs),
+    });
+
+    // Connection tasks ask the main task to stop, instead of exiting the process
+    // from under the log file writer.
+    let (terminate_sender, mut terminate_receiver) = mpsc::channel::<()>(1);
+
+    let context = Arc::new(ConnectionContext {
+        state: state_handle,
+        terminate_sender,
+        auth_token: options.auth_token.clone(),
+        http_allowed_origins: options.http_allowed_origins.clone(),
+        ws_allowed_origins: options.ws_allowed_origins.clone(),
+        metrics: Metrics::new(),
+    });
+
+    // When started through a systemd .socket unit, the listeners are already bound
+    let activated_listeners = match activated_listeners {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            error!(error = %e, "Could not use the sockets passed by systemd");
+            std::process::exit(1);
+        }
     };
 
-    let main_state = Arc::new(Mutex::new(TimeTrackingState  {
-        last_assigned_topic_id: 2,
-        current_topic_id: 0,
-        topics_tree: vec![],
-        details: TimeTrackingImplDetails::new()
-    }));
+    if activated_listeners.is_empty() {
+        for addr in options.listen.iter() {
+            let listener = match TcpListener::bind(addr).await {
+                Ok(l) => l,
+                Err(e) => {
+                    error!(%addr, error = %e, "Could not listen");
+                    std::process::exit(1);
+                }
+            };
+            info!(%addr, "Listening");
+            tokio::spawn(accept_connections(listener, context.clone()));
+        }
+    } else {
+        for std_listener in activated_listeners {
+            let listener = match TcpListener::from_std(std_listener) {
+                Ok(l) => l,
+                Err(e) => {
+                    error!(error = %e, "Could not use a socket passed by systemd");
+                    std::process::exit(1);
+                }
+            };
+            if let Ok(addr) = listener.local_addr() {
+                info!(%addr, "Listening on socket passed by systemd");
+            }
+            tokio::spawn(accept_connections(listener, context.clone()));
+        }
+    }
 
-    main_state.lock().unwrap().topics_tree.push(off_topic);
-    main_state.lock().unwrap().topics_tree.push(idle_topic);
-    main_state.lock().unwrap().topics_tree.push(example_topic);
+    for addr in options.http_listen.iter() {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                error!(%addr, error = %e, "Could not listen for HTTP");
+                std::process::exit(1);
+            }
+        };
+        info!(%addr, "Serving HTTP API");
+        tokio::spawn(http::accept_http_connections(listener, context.clone()));
+    }
+
+    for addr in options.ws_listen.iter() {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                error!(%addr, error = %e, "Could not listen for WebSocket");
+                std::process::exit(1);
+            }
+        };
+        info!(%addr, "Serving WebSocket endpoint");
+        tokio::spawn(websocket::accept_websocket_connections(listener, context.clone()));
+    }
 
-    let addr = env::args()
-        .nth(1)
-        .unwrap_or_else(|| "127.0.0.1:45862".to_string());
-    let listener = TcpListener::bind(&addr).await.unwrap();
-    println!("    Listening on: {}", addr);
+    let mut sigterm = signal(SignalKind::terminate()).expect("Cannot install SIGTERM handler");
+    tokio::select! {
+        _ = terminate_receiver.recv() => (),
+        _ = sigterm.recv() => {
+            info!("Received SIGTERM");
+            context.state.request(ClientRequest::Terminate{}).await;
+        }
+        _ = tokio::signal::ctrl_c() => {
+            info!("Received SIGINT");
+            context.state.request(ClientRequest::Terminate{}).await;
+        }
+    }
 
+    info!("TimeRacker core exiting...");
+}
 
+async fn accept_connections(listener: TcpListener, context: Arc<ConnectionContext>) {
     loop {
         match listener.accept().await {
-            Ok((socket, _)) => {
-                println!("    Accepted connection...");
-                let local_state = main_state.clone();
-
-                tokio::spawn(async move {
-                    let mut lines = Framed::new(socket, LinesCodec::new());
-
-                    while let Some(result) = lines.next().await {
-                        match result {
-                            Ok(line) => {
-                                let response = handle_request(&line, &local_state);
-
-                                let response_str = serde_json::to_string(&response).unwrap();
-                                if let Err(e) = lines.send(response_str.as_str()).await {
-                                    println!("[E] Error on sending response; error = {:?}", e);
-                                    println!("    Dropping connection");
-                                    break;
-                                }
-
-
-
-                                match response {
-                                    ResponseToClient::Bye {} => break,
-                                    ResponseToClient::Terminating {} => terminate_server(),
-                                    _ => ()
-                                }
-                            }
-                            Err(e) => {
-                                println!("[E] Error on decoding from socket; error = {:?}", e);
-                                println!("    Dropping connection");
-                                break;
-                            }
-                        }
-                    }
-                });
+            Ok((socket, peer_addr)) => {
+                let conn_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+                let span = info_span!("conn", id = conn_id);
+                tokio::spawn(serve_connection(socket, peer_addr, context.clone()).instrument(span));
             }
-            Err(e) => println!("error accepting socket; error = {:?}", e),
+            Err(e) => error!(error = ?e, "Error accepting socket"),
         }
     }
 }
 
+async fn serve_connection(socket: TcpStream, peer_addr: SocketAddr, context: Arc<ConnectionContext>) {
+    info!(%peer_addr, "Accepted connection");
+    context.metrics.client_connected();
 
-fn handle_request(line: &str, state: &Arc<Mutex<TimeTrackingState>>) -> ResponseToClient {
-    let request = match ClientRequest::parse(&line) {
-        Ok(req) => req,
-        Err(e) => return ResponseToClient::Error { error_code: 400, msg: e },
-    };
+    let mut lines = Framed::new(socket, LinesCodec::new());
+    let mut authenticated = context.auth_token.is_none();
 
-    // let mut topics = state.map.lock().unwrap();
-    match request {
-        ClientRequest::GetState{ } =>  {
-            println!("    Processing GET_STATE...");
-            let mut local_state_guard = state.lock().unwrap();
-            update_duration(&mut local_state_guard);
-            reset_current_topic_start_instant(&mut local_state_guard);
-            let response_string = serde_json::to_string(&(*local_state_guard)).unwrap();
-            ResponseToClient::State {value: response_string}
-        },
-
-        ClientRequest::Bye{ } =>  {
-            println!("    Processing BYE...");
-            ResponseToClient::Bye { }
-        },
-
-        ClientRequest::Terminate{ } =>  {
-            println!("    Processing TERMINATE...");
-            ResponseToClient::Terminating { }
-        },
-
-
-        ClientRequest::SwitchTopic { id } => {
-            println!("    Processing SWITCH_TOPIC...");
-            let mut local_state_guard = state.lock().unwrap();
-            let mut new_topic_id: Option<u64> = None;
-            let mut new_topic_name = String::new();
-            {
-                let topic = find_topic(id, &(local_state_guard.topics_tree));
-                if topic.is_some() {
-                    new_topic_id = Some(topic.unwrap().id);
-                    new_topic_name = topic.unwrap().name.clone();
-                } else {
+    while let Some(result) = lines.next().await {
+        match result {
+            Ok(line) => {
+                let response = handle_line(&line, &context, &mut authenticated).await;
+
+                let response_str = serde_json::to_string(&response).unwrap();
+                if let Err(e) = lines.send(response_str.as_str()).await {
+                    error!(error = ?e, "Error on sending response, dropping connection");
+                    break;
                 }
-            }
 
-            if new_topic_id.is_some() {
-                update_duration(&mut local_state_guard);
-                local_state_guard.current_topic_id = new_topic_id.unwrap();
-                reset_current_topic_start_instant(&mut local_state_guard);
-                println!("Switched topic to {} : {}", new_topic_id.unwrap(),  new_topic_name);
-                ResponseToClient::Success {details: format!("Switched topic to {}", new_topic_name)}
-            } else {
-                ResponseToClient::Error {error_code: 404, msg: "Topic not found".to_string()}
+                match response {
+                    ResponseToClient::Bye {} => break,
+                    ResponseToClient::Terminating {} => {
+                        let _ = context.terminate_sender.send(()).await;
+                        break;
+                    }
+                    _ => ()
+                }
+            }
+            Err(e) => {
+                error!(error = ?e, "Error on decoding from socket, dropping connection");
+                break;
             }
+        }
+    }
 
-        },
+    context.metrics.client_disconnected();
+    info!("Connection closed");
+}
 
-        /*
-        ClientRequest::CreateTopic { key, value } => {
-            let previous = db.insert(key.clone(), value.clone());
-            Response::Set {
-                key,
-                value,
-                previous,
-            }
-        },
-        */
 
-        _ => ResponseToClient::Error { error_code: 500, msg: "Server error".to_string()},
-    }
+async fn handle_line(line: &str, context: &ConnectionContext, authenticated: &mut bool) -> ResponseToClient {
+    let request = match ClientRequest::parse(line) {
+        Ok(req) => req,
+        Err(e) => {
+            warn!(%line, error = %e, "Rejected malformed request");
+            context.metrics.count_request("INVALID");
+            context.metrics.count_error(e.code());
+            return e.into();
+        }
+    };
+
+    handle_request(request, context, authenticated).await
 }
 
+// Entry point shared by every interface (TCP lines, HTTP, WebSocket), so that they never diverge
+async fn handle_request(request: ClientRequest, context: &ConnectionContext, authenticated: &mut bool) -> ResponseToClient {
+    let request_type = request.name();
+    debug!(request = request_type, "Processing request");
+    context.metrics.count_request(request_type);
 
+    let response = authenticate_then_process(request, context, authenticated).await;
+    if let ResponseToClient::Error { error_code, msg, .. } = &response {
+        warn!(request = request_type, error_code, %msg, "Request failed");
+        context.metrics.count_error(*error_code);
+    }
+    response
+}
 
+async fn authenticate_then_process(request: ClientRequest, context: &ConnectionContext, authenticated: &mut bool) -> ResponseToClient {
 
+    // Authentication is per connection, the state actor never sees AUTH
+    match (&request, &context.auth_token) {
+        (ClientRequest::Auth { token }, Some(expected_token)) => {
+            *authenticated = token == expected_token;
+            return if *authenticated {
+                ResponseToClient::Success { details: "Authenticated".to_string() }
+            } else {
+                ProtocolError::InvalidToken.into()
+            };
+        }
+        (ClientRequest::Auth { .. }, None) => {
+            return ResponseToClient::Success { details: "Authentication not required".to_string() };
+        }
+        (ClientRequest::Bye {}, _) => (),
+        _ if !*authenticated => {
+            return ProtocolError::AuthenticationRequired.into();
+        }
+        _ => (),
+    }
 
+    context.state.request(request).await
+}
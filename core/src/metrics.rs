@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Instant;
+
+use timeracker_common::TimeTrackingState;
+
+// Counters updated by the connection tasks, rendered in the Prometheus text format
+// along with the tracked time read from the state.
+pub struct Metrics {
+    start_instant: Instant,
+    connected_clients: AtomicI64,
+    requests_by_type: Mutex<BTreeMap<&'static str, u64>>,
+    errors_by_code: Mutex<BTreeMap<u64, u64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            start_instant: Instant::now(),
+            connected_clients: AtomicI64::new(0),
+            requests_by_type: Mutex::new(BTreeMap::new()),
+            errors_by_code: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn count_request(&self, request_type: &'static str) {
+        if let Ok(mut requests) = self.requests_by_type.lock() {
+            *requests.entry(request_type).or_insert(0) += 1;
+        }
+    }
+
+    pub fn count_error(&self, error_code: u64) {
+        if let Ok(mut errors) = self.errors_by_code.lock() {
+            *errors.entry(error_code).or_insert(0) += 1;
+        }
+    }
+
+    pub fn render(&self, state: &TimeTrackingState) -> String {
+        let mut out = String::new();
+
+        // A gauge, durations can be edited with UPDATE_TOPIC and go down
+        write_header(&mut out, "timeracker_topic_tracked_seconds", "gauge",
+                     "Time accumulated on each topic, in seconds.");
+        for topic in state.topics_tree.iter() {
+            let _ = writeln!(out, "timeracker_topic_tracked_seconds{{topic_id=\"{}\",topic=\"{}\",parent_id=\"{}\"}} {}",
+                             topic.id, escape_label_value(&topic.name), topic.parent_id, topic.duration);
+        }
+
+        write_header(&mut out, "timeracker_current_topic", "gauge",
+                     "Topic currently tracked, OFF (id 0) when tracking is disabled.");
+        if let Some(topic) = state.topics_tree.iter().find(|topic| topic.id == state.current_topic_id) {
+            let _ = writeln!(out, "timeracker_current_topic{{topic_id=\"{}\",topic=\"{}\"}} 1",
+                             topic.id, escape_label_value(&topic.name));
+        }
+
+        write_header(&mut out, "timeracker_current_topic_since_seconds", "gauge",
+                     "Unix time at which the current topic was switched to.");
+        let _ = writeln!(out, "timeracker_current_topic_since_seconds {}", state.current_topic_since);
+
+        write_header(&mut out, "timeracker_connected_clients", "gauge",
+                     "Clients connected through the line protocol or WebSocket.");
+        let _ = writeln!(out, "timeracker_connected_clients {}", self.connected_clients.load(Ordering::Relaxed));
+
+        write_header(&mut out, "timeracker_requests_total", "counter",
+                     "Requests received, by request type.");
+        if let Ok(requests) = self.requests_by_type.lock() {
+            for (request_type, count) in requests.iter() {
+                let _ = writeln!(out, "timeracker_requests_total{{request=\"{}\"}} {}", request_type, count);
+            }
+        }
+
+        write_header(&mut out, "timeracker_errors_total", "counter",
+                     "Error responses sent, by error code.");
+        if let Ok(errors) = self.errors_by_code.lock() {
+            for (error_code, count) in errors.iter() {
+                let _ = writeln!(out, "timeracker_errors_total{{error_code=\"{}\"}} {}", error_code, count);
+            }
+        }
+
+        write_header(&mut out, "timeracker_uptime_seconds", "gauge",
+                     "Time since the core started, in seconds.");
+        let _ = writeln!(out, "timeracker_uptime_seconds {}", self.start_instant.elapsed().as_secs());
+
+        out
+    }
+}
+
+impl ::std::default::Default for Metrics {
+    fn default() -> Self { Self::new() }
+}
+
+fn write_header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
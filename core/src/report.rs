@@ -0,0 +1,492 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use chrono::{Datelike, Duration, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use serde::{Serialize, Deserialize};
+
+use crate::{TimeTrackingInterval, TimeTrackingState};
+
+// Time spent per day, week or month, by topic, subtree or tag, computed from the intervals.
+// Every client builds its reports here, so that the numbers are the same everywhere.
+// Intervals are cut at period boundaries (local midnight, Monday, the 1st) and at the bounds of the report.
+// Tags are the #words of interval notes; an interval with several tags counts for each of them.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    // Starting on Monday
+    #[default]
+    Week,
+    Month,
+}
+
+pub const PERIODS: &[&str] = &["day", "week", "month"];
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            other => Err(format!("unknown period: {} (expected one of {})", other, PERIODS.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Grouping {
+    // One row per topic, with the time spent on the topic itself
+    Topic,
+    // The topic tree, each topic with the time spent on it and its descendants
+    #[default]
+    Subtree,
+    // One row per tag found in the notes
+    Tag,
+}
+
+pub const GROUPINGS: &[&str] = &["topic", "subtree", "tag"];
+
+impl FromStr for Grouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "topic" => Ok(Grouping::Topic),
+            "subtree" => Ok(Grouping::Subtree),
+            "tag" => Ok(Grouping::Tag),
+            other => Err(format!("unknown grouping: {} (expected one of {})", other, GROUPINGS.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for Grouping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Grouping::Topic => "topic",
+            Grouping::Subtree => "subtree",
+            Grouping::Tag => "tag",
+        };
+        f.write_str(name)
+    }
+}
+
+// Key of the row gathering the time of intervals without tags
+pub const UNTAGGED: &str = "(untagged)";
+
+pub struct ReportOptions {
+    pub period: Period,
+    pub grouping: Grouping,
+    // Bounds of the report, in seconds since the Unix epoch
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    // End of the running interval
+    pub now: u64,
+}
+
+impl ReportOptions {
+    pub fn new(now: u64) -> ReportOptions {
+        ReportOptions { period: Period::Week, grouping: Grouping::Subtree, since: None, until: None, now }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReportRow {
+    // Path of the topic, such as "Work/ClientA", or tag
+    pub key: String,
+    // None for tags
+    pub topic_id: Option<u64>,
+    // 0 for top-level topics, and when not grouped by subtree
+    pub depth: usize,
+    // Time spent on the topic itself, equal to total unless grouped by subtree
+    pub duration: u64,
+    pub total: u64,
+    // Share of the period total, in percent
+    pub percent: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReportPeriod {
+    // Such as "2026-10-18", "2026-W42" or "2026-10"
+    pub label: String,
+    pub start: u64,
+    pub end: u64,
+    pub total: u64,
+    pub rows: Vec<ReportRow>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Report {
+    pub period: Period,
+    pub grouping: Grouping,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub total: u64,
+    // Oldest first, only the periods with tracked time
+    pub periods: Vec<ReportPeriod>,
+}
+
+// Time spent per period, on each topic and on each tag
+#[derive(Default)]
+struct PeriodTotals {
+    topics: HashMap<u64, u64>,
+    tags: HashMap<String, u64>,
+    total: u64,
+}
+
+pub fn build_report(state: &TimeTrackingState, intervals: &[TimeTrackingInterval], options: &ReportOptions) -> Report {
+    let mut periods: BTreeMap<u64, PeriodTotals> = BTreeMap::new();
+    for interval in intervals {
+        let mut start = interval.start.max(options.since.unwrap_or(0));
+        let end = interval.end.unwrap_or(options.now).min(options.until.unwrap_or(u64::MAX));
+        let interval_tags = interval.note.as_deref().map(tags).unwrap_or_default();
+
+        while start < end {
+            let period_start = period_start(options.period, start);
+            let segment_end = end.min(next_period_start(options.period, period_start));
+            let secs = segment_end - start;
+
+            let totals = periods.entry(period_start).or_default();
+            *totals.topics.entry(interval.topic_id).or_insert(0) += secs;
+            if interval_tags.is_empty() {
+                *totals.tags.entry(UNTAGGED.to_string()).or_insert(0) += secs;
+            }
+            for tag in interval_tags.iter() {
+                *totals.tags.entry(tag.clone()).or_insert(0) += secs;
+            }
+            totals.total += secs;
+            start = segment_end;
+        }
+    }
+
+    let periods: Vec<ReportPeriod> = periods.into_iter().map(|(start, totals)| {
+        let mut rows = match options.grouping {
+            Grouping::Topic => topic_rows(state, &totals.topics),
+            Grouping::Subtree => subtree_rows(state, &totals.topics),
+            Grouping::Tag => totals.tags.iter()
+                .map(|(tag, secs)| row(tag.clone(), None, 0, *secs, *secs))
+                .collect(),
+        };
+        if options.grouping != Grouping::Subtree {
+            rows.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.key.cmp(&b.key)));
+        }
+        for row in rows.iter_mut() {
+            row.percent = percent(row.total, totals.total);
+        }
+        ReportPeriod {
+            label: period_label(options.period, start),
+            start,
+            end: next_period_start(options.period, start),
+            total: totals.total,
+            rows,
+        }
+    }).collect();
+
+    Report {
+        period: options.period,
+        grouping: options.grouping,
+        since: options.since,
+        until: options.until,
+        total: periods.iter().map(|period| period.total).sum(),
+        periods,
+    }
+}
+
+// #words of a note, lowercased, without duplicates, such as ["review", "client-a"] for "#Review of #client-a"
+pub fn tags(note: &str) -> Vec<String> {
+    let mut tags: Vec<String> = vec![];
+    for word in note.split_whitespace() {
+        let tag: String = match word.strip_prefix('#') {
+            Some(tag) => tag.chars().take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/')).collect(),
+            None => continue,
+        };
+        let tag = tag.to_lowercase();
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+fn row(key: String, topic_id: Option<u64>, depth: usize, duration: u64, total: u64) -> ReportRow {
+    ReportRow { key, topic_id, depth, duration, total, percent: 0.0 }
+}
+
+fn topic_rows(state: &TimeTrackingState, topics: &HashMap<u64, u64>) -> Vec<ReportRow> {
+    topics.iter().map(|(id, secs)| row(topic_path(state, *id), Some(*id), 0, *secs, *secs)).collect()
+}
+
+// Depth-first, siblings by name. Topics unknown to the state (deleted since) are listed at the top level.
+fn subtree_rows(state: &TimeTrackingState, topics: &HashMap<u64, u64>) -> Vec<ReportRow> {
+    let mut totals: HashMap<u64, u64> = HashMap::new();
+    for (id, secs) in topics.iter() {
+        for ancestor in ancestors(state, *id) {
+            *totals.entry(ancestor).or_insert(0) += secs;
+        }
+    }
+
+    let mut rows = vec![];
+    let mut stack: Vec<(u64, usize)> = children(state, 0).into_iter().rev().map(|id| (id, 0)).collect();
+    while let Some((id, depth)) = stack.pop() {
+        let total = totals.get(&id).copied().unwrap_or(0);
+        if total == 0 {
+            continue;
+        }
+        rows.push(row(topic_path(state, id), Some(id), depth, topics.get(&id).copied().unwrap_or(0), total));
+        stack.extend(children(state, id).into_iter().rev().map(|child| (child, depth + 1)));
+    }
+
+    let mut deleted: Vec<u64> = topics.keys().copied()
+        .filter(|id| !state.topics_tree.iter().any(|topic| topic.id == *id))
+        .collect();
+    deleted.sort_unstable();
+    rows.extend(deleted.into_iter().map(|id| row(topic_path(state, id), Some(id), 0, topics[&id], topics[&id])));
+    rows
+}
+
+// Children of `parent_id` by name, OFF aside
+fn children(state: &TimeTrackingState, parent_id: u64) -> Vec<u64> {
+    let mut children: Vec<(String, u64)> = state.topics_tree.iter()
+        .filter(|topic| topic.parent_id == parent_id && topic.id != 0 && topic.id != parent_id)
+        .map(|topic| (topic.name.to_lowercase(), topic.id))
+        .collect();
+    children.sort();
+    children.into_iter().map(|(_, id)| id).collect()
+}
+
+// `id` followed by its parent, grandparent... up to the top-level topic
+pub fn ancestors(state: &TimeTrackingState, id: u64) -> Vec<u64> {
+    let mut chain = vec![id];
+    let mut current = state.topics_tree.iter().find(|topic| topic.id == id);
+    while let Some(topic) = current {
+        if topic.parent_id == 0 || chain.contains(&topic.parent_id) {
+            break;
+        }
+        chain.push(topic.parent_id);
+        current = state.topics_tree.iter().find(|parent| parent.id == topic.parent_id);
+    }
+    chain
+}
+
+// Full path of a topic, such as "Work/ClientA"
+pub fn topic_path(state: &TimeTrackingState, id: u64) -> String {
+    let mut names: Vec<String> = ancestors(state, id).iter()
+        .map(|id| state.topics_tree.iter().find(|topic| topic.id == *id)
+            .map_or_else(|| format!("(deleted topic {})", id), |topic| topic.name.clone()))
+        .collect();
+    names.reverse();
+    names.join("/")
+}
+
+fn percent(secs: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => secs as f64 * 100.0 / total as f64,
+    }
+}
+
+// Start of the period containing `time`, in local time
+fn period_start(period: Period, time: u64) -> u64 {
+    let date = local_date(time);
+    let first_day = match period {
+        Period::Day => date,
+        Period::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        Period::Month => date.with_day(1).unwrap_or(date),
+    };
+    start_of_day(first_day)
+}
+
+fn next_period_start(period: Period, period_start: u64) -> u64 {
+    let date = local_date(period_start);
+    let next_day = match period {
+        Period::Day => date + Duration::days(1),
+        Period::Week => date + Duration::days(7),
+        Period::Month => date.checked_add_months(chrono::Months::new(1)).unwrap_or(date + Duration::days(31)),
+    };
+    // Guards against a period not moving forward, around a DST change at midnight
+    start_of_day(next_day).max(period_start + 1)
+}
+
+fn period_label(period: Period, period_start: u64) -> String {
+    let date = local_date(period_start);
+    match period {
+        Period::Day => date.format("%Y-%m-%d").to_string(),
+        Period::Week => date.format("%G-W%V").to_string(),
+        Period::Month => date.format("%Y-%m").to_string(),
+    }
+}
+
+// Date of `time` in the local time zone
+pub fn local_date(time: u64) -> NaiveDate {
+    Local.timestamp_opt(time as i64, 0).single().map_or_else(NaiveDate::default, |time| time.date_naive())
+}
+
+// Midnight, or the first valid time after it when a DST change skips midnight
+pub fn start_of_day(date: NaiveDate) -> u64 {
+    local_timestamp(date.and_time(NaiveTime::MIN))
+}
+
+// On DST changes, the earliest of the ambiguous times, or the first valid one after a gap
+pub fn local_timestamp(datetime: NaiveDateTime) -> u64 {
+    // Not LocalResult::earliest, which gives the first offset rather than the first instant
+    let first_instant = |datetime: &NaiveDateTime| match Local.from_local_datetime(datetime) {
+        LocalResult::Single(local) => Some(local),
+        LocalResult::Ambiguous(first, second) => Some(first.min(second)),
+        LocalResult::None => None,
+    };
+    let local = first_instant(&datetime).or_else(|| first_instant(&(datetime + Duration::hours(1))));
+    local.map_or(0, |local| local.timestamp().max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Once;
+    use crate::{TimeTrackingImplDetails, TimeTrackingTopic};
+
+    const HOUR: u64 = 3600;
+
+    // Central European time, whose DST changes of 2026 are on March 29 (23 h) and October 25 (25 h).
+    // Set once for the whole test binary, every test that reads local time expecting the same zone.
+    fn central_european_time() {
+        static SET_TZ: Once = Once::new();
+        SET_TZ.call_once(|| std::env::set_var("TZ", "CET-1CEST,M3.5.0,M10.5.0/3"));
+    }
+
+    // Local time on `day` (month, day of 2026), as seconds since the Unix epoch
+    fn at((month, day): (u32, u32), hour: u32, minute: u32) -> u64 {
+        local_timestamp(NaiveDate::from_ymd_opt(2026, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap())
+    }
+
+    // OFF, Idle, Work (2), Work/ClientA (3), Work/ClientA/Meetings (4) and Home (5)
+    fn state() -> TimeTrackingState {
+        let topics = [(0, "OFF", 0), (1, "Idle", 0), (2, "Work", 0), (3, "ClientA", 2), (4, "Meetings", 3), (5, "Home", 0)];
+        TimeTrackingState {
+            last_assigned_topic_id: 5,
+            current_topic_id: 0,
+            current_topic_since: 0,
+            topics_tree: topics.iter().map(|(id, name, parent_id)| TimeTrackingTopic::new(*id, name, *parent_id)).collect(),
+            details: TimeTrackingImplDetails::new()
+        }
+    }
+
+    fn interval(topic_id: u64, start: u64, end: Option<u64>, note: Option<&str>) -> TimeTrackingInterval {
+        TimeTrackingInterval { topic_id, start, end, note: note.map(str::to_string) }
+    }
+
+    fn report(intervals: &[TimeTrackingInterval], period: Period, grouping: Grouping, now: u64) -> Report {
+        let mut options = ReportOptions::new(now);
+        options.period = period;
+        options.grouping = grouping;
+        build_report(&state(), intervals, &options)
+    }
+
+    // (label, total) of each period
+    fn totals(report: &Report) -> Vec<(&str, u64)> {
+        report.periods.iter().map(|period| (period.label.as_str(), period.total)).collect()
+    }
+
+    #[test]
+    fn cuts_intervals_at_midnight() {
+        central_european_time();
+        let intervals = [interval(2, at((10, 18), 22, 0), Some(at((10, 19), 2, 0)), None)];
+
+        let by_day = report(&intervals, Period::Day, Grouping::Topic, at((10, 20), 0, 0));
+        assert_eq!(totals(&by_day), vec![("2026-10-18", 2 * HOUR), ("2026-10-19", 2 * HOUR)]);
+        assert_eq!((by_day.periods[0].start, by_day.periods[0].end), (at((10, 18), 0, 0), at((10, 19), 0, 0)));
+        assert_eq!(by_day.total, 4 * HOUR);
+
+        // Sunday night to Monday morning also crosses weeks, but not months
+        let by_week = report(&intervals, Period::Week, Grouping::Topic, at((10, 20), 0, 0));
+        assert_eq!(totals(&by_week), vec![("2026-W42", 2 * HOUR), ("2026-W43", 2 * HOUR)]);
+        let by_month = report(&intervals, Period::Month, Grouping::Topic, at((10, 20), 0, 0));
+        assert_eq!(totals(&by_month), vec![("2026-10", 4 * HOUR)]);
+    }
+
+    #[test]
+    fn days_of_dst_changes_last_23_and_25_hours() {
+        central_european_time();
+        let intervals = [interval(2, at((3, 28), 12, 0), Some(at((3, 30), 12, 0)), None),
+                         interval(2, at((10, 24), 12, 0), Some(at((10, 26), 12, 0)), None)];
+
+        let by_day = report(&intervals, Period::Day, Grouping::Topic, at((11, 1), 0, 0));
+        assert_eq!(totals(&by_day), vec![("2026-03-28", 12 * HOUR), ("2026-03-29", 23 * HOUR), ("2026-03-30", 12 * HOUR),
+                                         ("2026-10-24", 12 * HOUR), ("2026-10-25", 25 * HOUR), ("2026-10-26", 12 * HOUR)]);
+        // From midnight to midnight
+        let lengths: Vec<u64> = by_day.periods.iter().map(|period| (period.end - period.start) / HOUR).collect();
+        assert_eq!(lengths, vec![24, 23, 24, 24, 25, 24]);
+    }
+
+    #[test]
+    fn subtree_totals_roll_up_to_every_ancestor() {
+        central_european_time();
+        let start = at((10, 12), 9, 0);
+        let intervals = [interval(2, start, Some(start + HOUR), None),
+                         interval(3, start + HOUR, Some(start + 3 * HOUR), None),
+                         interval(4, start + 3 * HOUR, Some(start + 3 * HOUR + HOUR / 2), None),
+                         interval(5, start + 4 * HOUR, Some(start + 5 * HOUR), None)];
+
+        let report = report(&intervals, Period::Week, Grouping::Subtree, start + 6 * HOUR);
+        let rows: Vec<(&str, usize, u64, u64)> = report.periods[0].rows.iter()
+            .map(|row| (row.key.as_str(), row.depth, row.duration, row.total))
+            .collect();
+        assert_eq!(rows, vec![("Home", 0, HOUR, HOUR),
+                              ("Work", 0, HOUR, 3 * HOUR + HOUR / 2),
+                              ("Work/ClientA", 1, 2 * HOUR, 2 * HOUR + HOUR / 2),
+                              ("Work/ClientA/Meetings", 2, HOUR / 2, HOUR / 2)]);
+        assert_eq!(report.periods[0].total, 4 * HOUR + HOUR / 2);
+        let work = &report.periods[0].rows[1];
+        assert!((work.percent - 3.5 / 4.5 * 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tags_count_for_each_of_them_and_untagged_time_is_gathered() {
+        central_european_time();
+        let start = at((10, 12), 9, 0);
+        let intervals = [interval(2, start, Some(start + HOUR), Some("#Review of #client-a")),
+                         interval(3, start + HOUR, Some(start + 2 * HOUR), Some("#review again")),
+                         interval(5, start + 2 * HOUR, Some(start + 3 * HOUR), None)];
+
+        let report = report(&intervals, Period::Day, Grouping::Tag, start + 4 * HOUR);
+        let rows: Vec<(&str, Option<u64>, u64)> = report.periods[0].rows.iter()
+            .map(|row| (row.key.as_str(), row.topic_id, row.total))
+            .collect();
+        assert_eq!(rows, vec![("review", None, 2 * HOUR), (UNTAGGED, None, HOUR), ("client-a", None, HOUR)]);
+        assert_eq!(report.periods[0].total, 3 * HOUR, "tagged time is counted once in the total");
+    }
+
+    #[test]
+    fn running_interval_ends_now_and_bounds_cut_intervals() {
+        central_european_time();
+        let start = at((10, 18), 9, 0);
+        let intervals = [interval(2, start, Some(start + 2 * HOUR), None),
+                         interval(5, start + 2 * HOUR, None, None)];
+
+        let report = report(&intervals, Period::Day, Grouping::Topic, start + 3 * HOUR);
+        let rows: Vec<(&str, u64)> = report.periods[0].rows.iter().map(|row| (row.key.as_str(), row.total)).collect();
+        assert_eq!(rows, vec![("Work", 2 * HOUR), ("Home", HOUR)]);
+
+        let mut options = ReportOptions::new(start + 3 * HOUR);
+        options.period = Period::Day;
+        options.grouping = Grouping::Topic;
+        options.since = Some(start + HOUR);
+        options.until = Some(start + 2 * HOUR + HOUR / 2);
+        let bounded = build_report(&state(), &intervals, &options);
+        let rows: Vec<(&str, u64)> = bounded.periods[0].rows.iter().map(|row| (row.key.as_str(), row.total)).collect();
+        assert_eq!(rows, vec![("Work", HOUR), ("Home", HOUR / 2)]);
+    }
+}
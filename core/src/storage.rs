@@ -0,0 +1,118 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use tracing::warn;
+
+use timeracker_common::{TimeTrackingState, TimeTrackingTopic, TimeTrackingImplDetails, TimeTrackingInterval};
+
+pub const STATE_FILE_NAME: &str = "state.json";
+// One JSON interval per line, only ever appended to
+pub const INTERVALS_FILE_NAME: &str = "intervals.jsonl";
+
+// Returns None if there is no state file yet (first run)
+pub fn load_state(path: &Path) -> io::Result<Option<TimeTrackingState>> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let state = serde_json::from_str(&content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Some(state))
+}
+
+// Writes to a temporary file first, so that a crash mid-write never leaves a truncated state behind
+pub fn save_state(path: &Path, state: &TimeTrackingState) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let content = serde_json::to_string_pretty(state)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+// A last line without its newline was being appended when the core stopped: it is dropped,
+// and the file truncated before it. Any other invalid line is an error.
+pub fn load_intervals(path: &Path) -> io::Result<Vec<TimeTrackingInterval>> {
+    let content = match fs::read(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut intervals = vec![];
+    let mut offset = 0;
+    for (index, line) in content.split_inclusive(|byte| *byte == b'\n').enumerate() {
+        let complete = line.ends_with(b"\n");
+        if !line.iter().all(u8::is_ascii_whitespace) {
+            match serde_json::from_slice(line) {
+                Ok(interval) => intervals.push(interval),
+                Err(e) if !complete => {
+                    warn!(error = %e, path = %path.display(), line = index + 1, "Dropping the truncated interval at the end of the file");
+                    OpenOptions::new().write(true).open(path)?.set_len(offset as u64)?;
+                    return Ok(intervals);
+                }
+                Err(e) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", index + 1, e)));
+                }
+            }
+            // Complete but for its newline, which the next append must not run into
+            if !complete {
+                OpenOptions::new().append(true).open(path)?.write_all(b"\n")?;
+            }
+        }
+        offset += line.len();
+    }
+    Ok(intervals)
+}
+
+pub fn append_interval(path: &Path, interval: &TimeTrackingInterval) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let line = serde_json::to_string(interval)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+// State used on first run: the built-in OFF (0) and Idle (1) topics,
+// followed by the configured default topics.
+pub fn initial_state(default_topics: &[String]) -> TimeTrackingState {
+    let mut state = TimeTrackingState {
+        last_assigned_topic_id: 1,
+        current_topic_id: 0,
+        current_topic_since: 0,
+        topics_tree: vec![TimeTrackingTopic::new(0, "OFF", 0),
+                          TimeTrackingTopic::new(1, "Idle", 0)],
+        details: TimeTrackingImplDetails::new()
+    };
+
+    for path in default_topics {
+        let mut parent_id = 0;
+        for name in path.split('/').map(str::trim).filter(|name| !name.is_empty()) {
+            let existing = state.topics_tree.iter()
+                .find(|topic| topic.parent_id == parent_id && topic.name == name && topic.id != 0)
+                .map(|topic| topic.id);
+
+            parent_id = match existing {
+                Some(id) => id,
+                None => {
+                    state.last_assigned_topic_id += 1;
+                    let id = state.last_assigned_topic_id;
+                    state.topics_tree.push(TimeTrackingTopic::new(id, name, parent_id));
+                    id
+                }
+            };
+        }
+    }
+
+    state
+}
@@ -0,0 +1,60 @@
+use std::env;
+use std::io;
+use std::mem;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+// First file descriptor passed by systemd, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+// Takes the listening sockets passed by systemd socket activation, if any.
+// Returns an empty list when the core was not started through a .socket unit.
+// Clears the LISTEN_* variables, so it must run before any other thread is started:
+// changing the environment while another thread reads it is undefined behaviour.
+pub fn take_listeners() -> io::Result<Vec<TcpListener>> {
+    let listen_pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let listen_fds = env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<RawFd>().ok());
+
+    // Never hand these down to a child process
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let listen_fds = match (listen_pid, listen_fds) {
+        (Some(pid), Some(fds)) if pid == std::process::id() => fds,
+        _ => return Ok(vec![]),
+    };
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + listen_fds)
+        .map(|fd| {
+            // A .socket unit may also pass datagram, FIFO or Unix sockets, which the core cannot serve
+            if !is_tcp_listener(fd) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("file descriptor {} is not a listening TCP socket", fd)));
+            }
+            // Safety: systemd guarantees these descriptors are open and owned by this process
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+fn is_tcp_listener(fd: RawFd) -> bool {
+    let socket_option = |level, name| -> Option<libc::c_int> {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // Safety: value and len point to a c_int and its size, as getsockopt expects
+        let result = unsafe { libc::getsockopt(fd, level, name, &mut value as *mut _ as *mut libc::c_void, &mut len) };
+        if result == 0 { Some(value) } else { None }
+    };
+
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut addr_len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    // Safety: addr is large enough for any socket address
+    let named = unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut addr_len) } == 0;
+    let inet = named && (addr.ss_family as libc::c_int == libc::AF_INET || addr.ss_family as libc::c_int == libc::AF_INET6);
+
+    inet && socket_option(libc::SOL_SOCKET, libc::SO_TYPE) == Some(libc::SOCK_STREAM)
+        && socket_option(libc::SOL_SOCKET, libc::SO_ACCEPTCONN) == Some(1)
+}
@@ -0,0 +1,344 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures::SinkExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::stream::StreamExt;
+use tokio::sync::oneshot;
+use tokio_util::codec::{Framed, LinesCodec};
+
+use crate::{ClientOptions, ClientRequest, FakeClock, ProtocolError, ResponseToClient, TimeTracker,
+            TimeTrackingInterval, TimeTrackingState};
+
+// Stand-in for timeracker_core, to test clients without starting the real binary.
+// Listens on an ephemeral port of 127.0.0.1, serves the line protocol from a TimeTracker
+// driven by a FakeClock, and misbehaves on demand. Nothing is persisted.
+
+// Misbehaviour applied to the next request received, on any connection
+#[derive(Clone, Debug)]
+pub enum Fault {
+    // Closes the connection instead of answering
+    DropConnection,
+    // Answers with a line that is not valid JSON
+    MalformedJson,
+    // Waits (for real) before answering normally
+    Delay(Duration),
+}
+
+struct Shared {
+    tracker: Mutex<TimeTracker<FakeClock>>,
+    faults: Mutex<VecDeque<Fault>>,
+    // Every line received, in order
+    requests: Mutex<Vec<String>>,
+}
+
+pub struct TestServer {
+    addr: SocketAddr,
+    clock: FakeClock,
+    shared: Arc<Shared>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl TestServer {
+    // Starts serving `state`. The clock starts at `state.current_topic_since`.
+    pub async fn start(state: TimeTrackingState) -> io::Result<TestServer> {
+        TestServer::start_with_intervals(state, vec![]).await
+    }
+
+    pub async fn start_with_intervals(state: TimeTrackingState, intervals: Vec<TimeTrackingInterval>) -> io::Result<TestServer> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let clock = FakeClock::new(state.current_topic_since);
+        let shared = Arc::new(Shared {
+            tracker: Mutex::new(TimeTracker::new(state, intervals, clock.clone())),
+            faults: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(vec![]),
+        });
+
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+        tokio::spawn(accept_connections(listener, shared.clone(), shutdown_receiver));
+
+        Ok(TestServer { addr, clock, shared, shutdown: Some(shutdown) })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Options for a client of this server, with short timeouts
+    pub fn client_options(&self) -> ClientOptions {
+        let mut options = ClientOptions::new();
+        options.server = self.addr.to_string();
+        options.connect_timeout = Duration::from_millis(500);
+        options.request_timeout = Duration::from_millis(500);
+        options.reconnect_delay = Duration::from_millis(10);
+        options
+    }
+
+    pub fn clock(&self) -> &FakeClock {
+        &self.clock
+    }
+
+    // Faults are applied in the order they were injected, one per request
+    pub fn inject_fault(&self, fault: Fault) {
+        self.shared.faults.lock().unwrap().push_back(fault);
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+
+    // Inspects or changes the served state directly
+    pub fn with_tracker<R, F: FnOnce(&mut TimeTracker<FakeClock>) -> R>(&self, f: F) -> R {
+        f(&mut self.shared.tracker.lock().unwrap())
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+async fn accept_connections(listener: TcpListener, shared: Arc<Shared>, mut shutdown: oneshot::Receiver<()>) {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                if let Ok((socket, _)) = accepted {
+                    tokio::spawn(serve_connection(socket, shared.clone()));
+                }
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+}
+
+async fn serve_connection(socket: TcpStream, shared: Arc<Shared>) {
+    let mut lines = Framed::new(socket, LinesCodec::new());
+
+    while let Some(Ok(line)) = lines.next().await {
+        shared.requests.lock().unwrap().push(line.clone());
+
+        let fault = shared.faults.lock().unwrap().pop_front();
+        match fault {
+            Some(Fault::DropConnection) => return,
+            Some(Fault::MalformedJson) => {
+                if lines.send("{\"type\": \"State\", \"value\"").await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            Some(Fault::Delay(delay)) => tokio::time::sleep(delay).await,
+            None => (),
+        }
+
+        let response = match ClientRequest::parse(line.trim()) {
+            Ok(request) => process_request(request, &shared),
+            Err(e) => e.into(),
+        };
+        let done = matches!(response, ResponseToClient::Bye {} | ResponseToClient::Terminating {});
+
+        if lines.send(serde_json::to_string(&response).unwrap()).await.is_err() || done {
+            return;
+        }
+    }
+}
+
+// Same answers as the core, minus authentication (any token is accepted) and persistence
+fn process_request(request: ClientRequest, shared: &Shared) -> ResponseToClient {
+    let mut tracker = shared.tracker.lock().unwrap();
+    if request.is_activity() {
+        tracker.record_activity();
+    }
+    match request {
+        ClientRequest::GetState {} => ResponseToClient::State { value: serde_json::to_string(tracker.state()).unwrap() },
+        ClientRequest::SwitchTopic { id } => match tracker.switch_topic(id) {
+            Ok(switch) => ResponseToClient::Success { details: format!("Switched topic to {}", switch.topic_name) },
+            Err(e) => e.into(),
+        },
+        ClientRequest::CreateTopic { name, parent_id } => match tracker.create_topic(&name, parent_id) {
+            Ok((id, _)) => ResponseToClient::Success { details: format!("Created topic {} with id {}", name, id) },
+            Err(e) => e.into(),
+        },
+        ClientRequest::UpdateTopic { id, name, parent_id, duration } => match tracker.update_topic(id, &name, parent_id, duration) {
+            Ok(_) => ResponseToClient::Success { details: format!("Updated topic {}", id) },
+            Err(e) => e.into(),
+        },
+        ClientRequest::GetIntervals { since, until } => ResponseToClient::Intervals { intervals: tracker.intervals_between(since, until) },
+        ClientRequest::SetNote { note } => match tracker.set_note(note) {
+            Ok(_) => ResponseToClient::Success { details: "Note set".to_string() },
+            Err(e) => e.into(),
+        },
+        ClientRequest::Activity {} => ResponseToClient::Success { details: "Activity recorded".to_string() },
+        ClientRequest::Auth { .. } => ResponseToClient::Success { details: "Authenticated".to_string() },
+        ClientRequest::Bye {} => ResponseToClient::Bye {},
+        ClientRequest::Terminate {} => ResponseToClient::Terminating {},
+        other => ProtocolError::Unsupported { command: other.name() }.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::task;
+    use crate::{BlockingTimeRackerClient, ClientError, TimeRackerClient, TimeTrackingImplDetails, TimeTrackingTopic};
+
+    // Longer than the request timeout of options()
+    const SLOW: Duration = Duration::from_millis(300);
+
+    fn state() -> TimeTrackingState {
+        TimeTrackingState {
+            last_assigned_topic_id: 2,
+            current_topic_id: 0,
+            current_topic_since: 1_700_000_000,
+            topics_tree: vec![TimeTrackingTopic::new(0, "OFF", 0),
+                              TimeTrackingTopic::new(1, "Idle", 0),
+                              TimeTrackingTopic::new(2, "Work", 0)],
+            details: TimeTrackingImplDetails::new()
+        }
+    }
+
+    fn options(server: &TestServer) -> ClientOptions {
+        let mut options = server.client_options();
+        options.request_timeout = Duration::from_millis(100);
+        options
+    }
+
+    // How many times `command` reached the server
+    fn received(server: &TestServer, command: &str) -> usize {
+        server.requests().iter().filter(|line| line.split(' ').next() == Some(command)).count()
+    }
+
+    fn topics_named(server: &TestServer, name: &str) -> usize {
+        server.with_tracker(|tracker| tracker.state().topics_tree.iter().filter(|topic| topic.name == name).count())
+    }
+
+    async fn blocking<R: Send + 'static>(f: impl FnOnce() -> R + Send + 'static) -> R {
+        task::spawn_blocking(f).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn async_client_reconnects_and_retries_after_a_dropped_connection() {
+        let server = TestServer::start(state()).await.unwrap();
+        let mut client = TimeRackerClient::connect(options(&server)).await.unwrap();
+
+        server.inject_fault(Fault::DropConnection);
+        assert_eq!(client.get_state().await.unwrap().topics_tree.len(), 3);
+        assert_eq!(received(&server, "GET_STATE"), 2);
+    }
+
+    #[tokio::test]
+    async fn blocking_client_reconnects_and_retries_after_a_dropped_connection() {
+        let server = TestServer::start(state()).await.unwrap();
+        let options = options(&server);
+
+        server.inject_fault(Fault::DropConnection);
+        let state = blocking(move || BlockingTimeRackerClient::connect(options)?.get_state()).await;
+        assert_eq!(state.unwrap().topics_tree.len(), 3);
+        assert_eq!(received(&server, "GET_STATE"), 2);
+    }
+
+    #[tokio::test]
+    async fn async_client_does_not_retry_create_topic_after_a_dropped_connection() {
+        let server = TestServer::start(state()).await.unwrap();
+        let mut client = TimeRackerClient::connect(options(&server)).await.unwrap();
+
+        server.inject_fault(Fault::DropConnection);
+        let error = client.create_topic("Home", 0).await.unwrap_err();
+        assert!(error.is_transport(), "{}", error);
+        assert_eq!(received(&server, "CREATE_TOPIC"), 1);
+        assert_eq!(topics_named(&server, "Home"), 0);
+
+        // The next request reconnects
+        client.create_topic("Home", 0).await.unwrap();
+        assert_eq!(topics_named(&server, "Home"), 1);
+    }
+
+    #[tokio::test]
+    async fn blocking_client_does_not_retry_create_topic_after_a_dropped_connection() {
+        let server = TestServer::start(state()).await.unwrap();
+        let options = options(&server);
+
+        server.inject_fault(Fault::DropConnection);
+        let (first, second) = blocking(move || {
+            let mut client = BlockingTimeRackerClient::connect(options).unwrap();
+            (client.create_topic("Home", 0), client.create_topic("Home", 0))
+        }).await;
+        assert!(first.unwrap_err().is_transport());
+        second.unwrap();
+        assert_eq!(received(&server, "CREATE_TOPIC"), 2);
+        assert_eq!(topics_named(&server, "Home"), 1);
+    }
+
+    #[tokio::test]
+    async fn async_client_reports_malformed_json_and_keeps_the_connection() {
+        let server = TestServer::start(state()).await.unwrap();
+        let mut client = TimeRackerClient::connect(options(&server)).await.unwrap();
+
+        server.inject_fault(Fault::MalformedJson);
+        assert!(matches!(client.get_state().await, Err(ClientError::Decode(_))));
+        assert_eq!(received(&server, "GET_STATE"), 1, "not a transport error, not retried");
+        client.get_state().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn blocking_client_reports_malformed_json_and_keeps_the_connection() {
+        let server = TestServer::start(state()).await.unwrap();
+        let options = options(&server);
+
+        server.inject_fault(Fault::MalformedJson);
+        let (first, second) = blocking(move || {
+            let mut client = BlockingTimeRackerClient::connect(options).unwrap();
+            (client.get_state(), client.get_state())
+        }).await;
+        assert!(matches!(first, Err(ClientError::Decode(_))));
+        second.unwrap();
+        assert_eq!(received(&server, "GET_STATE"), 2);
+    }
+
+    #[tokio::test]
+    async fn async_client_times_out_then_retries_only_what_is_safe_to_repeat() {
+        let server = TestServer::start(state()).await.unwrap();
+        let mut client = TimeRackerClient::connect(options(&server)).await.unwrap();
+
+        server.inject_fault(Fault::Delay(SLOW));
+        client.get_state().await.unwrap();
+        assert_eq!(received(&server, "GET_STATE"), 2);
+
+        server.inject_fault(Fault::Delay(SLOW));
+        assert!(matches!(client.create_topic("Home", 0).await, Err(ClientError::Timeout)));
+        assert_eq!(received(&server, "CREATE_TOPIC"), 1);
+
+        // The late request was still carried out, once
+        tokio::time::sleep(SLOW).await;
+        assert_eq!(topics_named(&server, "Home"), 1);
+    }
+
+    #[tokio::test]
+    async fn blocking_client_times_out_then_retries_only_what_is_safe_to_repeat() {
+        let server = TestServer::start(state()).await.unwrap();
+        let options = options(&server);
+
+        server.inject_fault(Fault::Delay(SLOW));
+        let (mut client, state) = blocking(move || {
+            let mut client = BlockingTimeRackerClient::connect(options).unwrap();
+            let state = client.get_state();
+            (client, state)
+        }).await;
+        state.unwrap();
+        assert_eq!(received(&server, "GET_STATE"), 2);
+
+        server.inject_fault(Fault::Delay(SLOW));
+        let created = blocking(move || client.create_topic("Home", 0)).await;
+        assert!(matches!(created, Err(ClientError::Timeout)));
+        assert_eq!(received(&server, "CREATE_TOPIC"), 1);
+
+        tokio::time::sleep(SLOW).await;
+        assert_eq!(topics_named(&server, "Home"), 1);
+    }
+}
@@ -0,0 +1,439 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{ProtocolError, StateChangeEvent, TimeTrackingInterval, TimeTrackingState, TimeTrackingTopic};
+
+// Tracking logic, without any networking or persistence: accumulating time on the current topic,
+// switching topics, recording intervals and falling back to Idle/OFF after inactivity.
+// The core runs one behind its state actor; applications can embed one directly.
+
+// Source of time for the tracker, replaceable by a FakeClock in tests
+pub trait Clock {
+    // Monotonic, used to measure the time spent on topics
+    fn instant(&self) -> Instant;
+    // Wall clock, in seconds since the Unix epoch, used for interval bounds
+    fn unix_now(&self) -> u64;
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+// Clock that only moves when told to. Clones share the same time.
+#[derive(Clone)]
+pub struct FakeClock {
+    start_instant: Instant,
+    start_unix: u64,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl FakeClock {
+    pub fn new(start_unix: u64) -> FakeClock {
+        FakeClock { start_instant: Instant::now(), start_unix, elapsed: Arc::new(Mutex::new(Duration::from_secs(0))) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Clock for FakeClock {
+    fn instant(&self) -> Instant {
+        self.start_instant + self.elapsed()
+    }
+
+    fn unix_now(&self) -> u64 {
+        self.start_unix + self.elapsed().as_secs()
+    }
+}
+
+// What a successful switch changed, for the caller to persist and publish
+pub struct TopicSwitch {
+    pub topic_name: String,
+    // None when nothing was tracked since the previous switch (OFF, or no time elapsed)
+    pub closed_interval: Option<TimeTrackingInterval>,
+    pub event: StateChangeEvent,
+}
+
+pub struct TimeTracker<C: Clock = SystemClock> {
+    state: TimeTrackingState,
+    // Closed intervals, the running one is derived from the state
+    intervals: Vec<TimeTrackingInterval>,
+    // Note of the running interval, dropped on switch
+    current_note: Option<String>,
+    clock: C,
+    // Last switch or reported activity, see check_idle
+    last_activity_instant: Instant,
+}
+
+impl TimeTracker<SystemClock> {
+    pub fn with_system_clock(state: TimeTrackingState, intervals: Vec<TimeTrackingInterval>) -> TimeTracker<SystemClock> {
+        TimeTracker::new(state, intervals, SystemClock)
+    }
+}
+
+impl<C: Clock> TimeTracker<C> {
+    // The current topic of `state` is considered switched to right now
+    pub fn new(mut state: TimeTrackingState, intervals: Vec<TimeTrackingInterval>, clock: C) -> TimeTracker<C> {
+        let now = clock.instant();
+        state.current_topic_since = clock.unix_now();
+        state.details.current_topic_start_instant = now;
+        TimeTracker { state, intervals, current_note: None, clock, last_activity_instant: now }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    // Up-to-date state, with the time spent on the current topic so far accounted for
+    pub fn state(&mut self) -> &TimeTrackingState {
+        self.accumulate();
+        &self.state
+    }
+
+    pub fn find_topic(&self, id: u64) -> Option<&TimeTrackingTopic> {
+        self.state.topics_tree.iter().find(|topic| topic.id == id)
+    }
+
+    pub fn current_topic_id(&self) -> u64 {
+        self.state.current_topic_id
+    }
+
+    // Closed intervals, followed by the running one unless tracking is OFF
+    pub fn intervals(&self) -> Vec<TimeTrackingInterval> {
+        let mut intervals = self.intervals.clone();
+        if self.state.current_topic_id != 0 {
+            intervals.push(TimeTrackingInterval {
+                topic_id: self.state.current_topic_id,
+                start: self.state.current_topic_since,
+                end: None,
+                note: self.current_note.clone()
+            });
+        }
+        intervals
+    }
+
+    // Intervals overlapping [since, until), whole rather than cut at the bounds
+    pub fn intervals_between(&self, since: Option<u64>, until: Option<u64>) -> Vec<TimeTrackingInterval> {
+        let (since, until) = (since.unwrap_or(0), until.unwrap_or(u64::MAX));
+        self.intervals().into_iter()
+            .filter(|interval| interval.start < until && interval.end.is_none_or(|end| end > since))
+            .collect()
+    }
+
+    pub fn since_last_activity(&self) -> Duration {
+        self.clock.instant().saturating_duration_since(self.last_activity_instant)
+    }
+
+    // The user is at work, on whatever topic is current
+    pub fn record_activity(&mut self) {
+        self.last_activity_instant = self.clock.instant();
+    }
+
+    // Adds the whole seconds elapsed on the current topic to its duration.
+    // The remainder is carried over, so that frequent calls do not lose time.
+    pub fn accumulate(&mut self) {
+        let now = self.clock.instant();
+        let details = &mut self.state.details;
+        let elapsed_secs = now.saturating_duration_since(details.current_topic_start_instant).as_secs();
+        details.current_topic_start_instant += Duration::from_secs(elapsed_secs);
+
+        let current_topic_id = self.state.current_topic_id;
+        if let Some(topic) = self.state.topics_tree.iter_mut().find(|topic| topic.id == current_topic_id) {
+            topic.duration += elapsed_secs;
+        }
+    }
+
+    pub fn switch_topic(&mut self, id: u64) -> Result<TopicSwitch, ProtocolError> {
+        let topic_name = self.find_topic(id).ok_or(ProtocolError::TopicNotFound { id })?.name.clone();
+
+        let previous_topic_id = self.state.current_topic_id;
+        self.accumulate();
+        let closed_interval = self.close_current_interval();
+        self.state.current_topic_id = id;
+        self.current_note = None;
+        // Less than a second may be left on the previous topic, it is dropped
+        self.state.details.current_topic_start_instant = self.clock.instant();
+        self.last_activity_instant = self.clock.instant();
+
+        Ok(TopicSwitch {
+            topic_name,
+            closed_interval,
+            event: StateChangeEvent::TopicSwitched {
+                previous_topic_id,
+                topic_id: id,
+                since: self.state.current_topic_since
+            }
+        })
+    }
+
+    // Returns the id of the new topic. Names are unique (case-insensitively) among siblings.
+    pub fn create_topic(&mut self, name: &str, parent_id: u64) -> Result<(u64, StateChangeEvent), ProtocolError> {
+        check_topic_name("CREATE_TOPIC", name)?;
+        self.check_parent(parent_id)?;
+        self.check_unique_name(name, parent_id, None)?;
+
+        self.state.last_assigned_topic_id += 1;
+        let id = self.state.last_assigned_topic_id;
+        self.state.topics_tree.push(TimeTrackingTopic::new(id, name, parent_id));
+
+        Ok((id, StateChangeEvent::TopicCreated { topic_id: id, name: name.to_string(), parent_id }))
+    }
+
+    // Renames, moves and sets the duration of a topic. OFF and Idle cannot be changed,
+    // and a topic cannot be moved under itself or one of its descendants.
+    pub fn update_topic(&mut self, id: u64, name: &str, parent_id: u64, duration: u64) -> Result<StateChangeEvent, ProtocolError> {
+        if id == 0 || id == 1 {
+            return Err(ProtocolError::Conflict { msg: "OFF and Idle cannot be changed".to_string() });
+        }
+        self.find_topic(id).ok_or(ProtocolError::TopicNotFound { id })?;
+        check_topic_name("UPDATE_TOPIC", name)?;
+        self.check_parent(parent_id)?;
+
+        let mut ancestor_id = parent_id;
+        while ancestor_id != 0 {
+            if ancestor_id == id {
+                return Err(ProtocolError::Conflict { msg: "A topic cannot be moved under itself".to_string() });
+            }
+            ancestor_id = self.find_topic(ancestor_id).map_or(0, |topic| topic.parent_id);
+        }
+        self.check_unique_name(name, parent_id, Some(id))?;
+
+        // Time spent on the current topic until now is part of the duration being replaced
+        self.accumulate();
+        if let Some(topic) = self.state.topics_tree.iter_mut().find(|topic| topic.id == id) {
+            topic.name = name.to_string();
+            topic.parent_id = parent_id;
+            topic.duration = duration;
+        }
+
+        Ok(StateChangeEvent::TopicUpdated { topic_id: id, name: name.to_string(), parent_id, duration })
+    }
+
+    // Sets or clears the note of the running interval
+    pub fn set_note(&mut self, note: Option<String>) -> Result<StateChangeEvent, ProtocolError> {
+        if self.state.current_topic_id == 0 {
+            return Err(ProtocolError::Conflict { msg: "No interval is running while tracking is OFF".to_string() });
+        }
+        self.current_note = note.clone();
+        Ok(StateChangeEvent::NoteSet { topic_id: self.state.current_topic_id, note })
+    }
+
+    fn check_parent(&self, parent_id: u64) -> Result<(), ProtocolError> {
+        match parent_id {
+            0 => Ok(()),
+            1 => Err(ProtocolError::Conflict { msg: "Idle cannot have subtopics".to_string() }),
+            _ => self.find_topic(parent_id).map(|_| ()).ok_or(ProtocolError::ParentNotFound { parent_id }),
+        }
+    }
+
+    // Names are unique (case-insensitively) among siblings, `except_id` being the topic renamed
+    fn check_unique_name(&self, name: &str, parent_id: u64, except_id: Option<u64>) -> Result<(), ProtocolError> {
+        let lowercase_name = name.to_lowercase();
+        let exists = self.state.topics_tree.iter()
+            .any(|topic| topic.parent_id == parent_id && topic.id != 0 && Some(topic.id) != except_id
+                && topic.name.to_lowercase() == lowercase_name);
+        match exists {
+            true => Err(ProtocolError::Conflict { msg: format!("Topic {} already exists", name) }),
+            false => Ok(()),
+        }
+    }
+
+    // Ends the running interval (if anything was tracked) and starts a new one on the same topic
+    pub fn close_current_interval(&mut self) -> Option<TimeTrackingInterval> {
+        let now = self.clock.unix_now();
+        let state = &mut self.state;
+        let mut closed_interval = None;
+        if state.current_topic_id != 0 && state.current_topic_since < now {
+            let interval = TimeTrackingInterval {
+                topic_id: state.current_topic_id,
+                start: state.current_topic_since,
+                end: Some(now),
+                note: self.current_note.clone()
+            };
+            self.intervals.push(interval.clone());
+            closed_interval = Some(interval);
+        }
+        state.current_topic_since = now;
+        closed_interval
+    }
+
+    // Falls back from a topic to Idle after `idle_after` without activity, and from Idle to OFF
+    // after `off_after`. Activity is a switch or anything passed to record_activity: the core cannot
+    // see the user, clients report them with ACTIVITY. Returns the switch made, if any.
+    pub fn check_idle(&mut self, idle_after: Option<Duration>, off_after: Option<Duration>) -> Option<TopicSwitch> {
+        let threshold = match self.state.current_topic_id {
+            0 => None,
+            1 => off_after.map(|off_after| (off_after, 0)),
+            _ => idle_after.map(|idle_after| (idle_after, 1)),
+        };
+
+        match threshold {
+            Some((after, next_topic_id)) if self.since_last_activity() >= after => self.switch_topic(next_topic_id).ok(),
+            _ => None,
+        }
+    }
+}
+
+fn check_topic_name(command: &'static str, name: &str) -> Result<(), ProtocolError> {
+    if name.is_empty() || name.contains(char::is_whitespace) || name.contains('/') {
+        return Err(ProtocolError::InvalidArgument {
+            command,
+            field: "name",
+            expected: "a non-empty name without spaces or '/'"
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TimeTrackingImplDetails;
+
+    const START: u64 = 1_700_000_000;
+
+    // OFF, Idle, Work (2), Work/ClientA (3), Work/ClientA/Meetings (4) and Home (5), tracking OFF
+    fn tracker() -> (TimeTracker<FakeClock>, FakeClock) {
+        let state = TimeTrackingState {
+            last_assigned_topic_id: 5,
+            current_topic_id: 0,
+            current_topic_since: 0,
+            topics_tree: vec![TimeTrackingTopic::new(0, "OFF", 0),
+                              TimeTrackingTopic::new(1, "Idle", 0),
+                              TimeTrackingTopic::new(2, "Work", 0),
+                              TimeTrackingTopic::new(3, "ClientA", 2),
+                              TimeTrackingTopic::new(4, "Meetings", 3),
+                              TimeTrackingTopic::new(5, "Home", 0)],
+            details: TimeTrackingImplDetails::new()
+        };
+        let clock = FakeClock::new(START);
+        (TimeTracker::new(state, vec![], clock.clone()), clock)
+    }
+
+    fn duration(tracker: &mut TimeTracker<FakeClock>, id: u64) -> u64 {
+        tracker.state().topics_tree.iter().find(|topic| topic.id == id).unwrap().duration
+    }
+
+    fn conflict(result: Result<StateChangeEvent, ProtocolError>) -> bool {
+        matches!(result, Err(ProtocolError::Conflict { .. }))
+    }
+
+    #[test]
+    fn accumulate_carries_the_remainder_over() {
+        let (mut tracker, clock) = tracker();
+        tracker.switch_topic(2).unwrap();
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(duration(&mut tracker, 2), 1);
+        clock.advance(Duration::from_millis(700));
+        assert_eq!(duration(&mut tracker, 2), 2);
+        clock.advance(Duration::from_millis(800));
+        assert_eq!(duration(&mut tracker, 2), 3);
+        assert_eq!(duration(&mut tracker, 5), 0);
+    }
+
+    #[test]
+    fn switch_topic_closes_the_running_interval_and_opens_a_new_one() {
+        let (mut tracker, clock) = tracker();
+
+        let switch = tracker.switch_topic(2).unwrap();
+        assert_eq!(switch.topic_name, "Work");
+        assert!(switch.closed_interval.is_none(), "nothing is tracked while OFF");
+        assert!(matches!(switch.event, StateChangeEvent::TopicSwitched { previous_topic_id: 0, topic_id: 2, since: START }));
+
+        clock.advance(Duration::from_secs(60));
+        let switch = tracker.switch_topic(5).unwrap();
+        let closed = switch.closed_interval.unwrap();
+        assert_eq!((closed.topic_id, closed.start, closed.end), (2, START, Some(START + 60)));
+        assert_eq!(duration(&mut tracker, 2), 60);
+
+        let intervals = tracker.intervals();
+        assert_eq!(intervals.len(), 2);
+        assert_eq!((intervals[1].topic_id, intervals[1].start, intervals[1].end), (5, START + 60, None));
+
+        clock.advance(Duration::from_secs(30));
+        tracker.switch_topic(0).unwrap();
+        let intervals = tracker.intervals();
+        assert_eq!(intervals.len(), 2, "no interval runs while OFF");
+        assert_eq!((intervals[1].topic_id, intervals[1].end), (5, Some(START + 90)));
+
+        assert_eq!(tracker.switch_topic(42).err(), Some(ProtocolError::TopicNotFound { id: 42 }));
+        assert_eq!(tracker.current_topic_id(), 0);
+    }
+
+    #[test]
+    fn check_idle_falls_back_to_idle_then_off() {
+        let (mut tracker, clock) = tracker();
+        let (idle_after, off_after) = (Some(Duration::from_secs(300)), Some(Duration::from_secs(600)));
+        tracker.switch_topic(2).unwrap();
+
+        clock.advance(Duration::from_secs(299));
+        assert!(tracker.check_idle(idle_after, off_after).is_none());
+        clock.advance(Duration::from_secs(1));
+        assert!(tracker.check_idle(None, off_after).is_none(), "no idle threshold, no switch");
+        let switch = tracker.check_idle(idle_after, off_after).unwrap();
+        assert_eq!(switch.topic_name, "Idle");
+        assert_eq!(tracker.current_topic_id(), 1);
+
+        clock.advance(Duration::from_secs(599));
+        assert!(tracker.check_idle(idle_after, off_after).is_none());
+        clock.advance(Duration::from_secs(1));
+        tracker.check_idle(idle_after, off_after).unwrap();
+        assert_eq!(tracker.current_topic_id(), 0);
+
+        clock.advance(Duration::from_secs(3600));
+        assert!(tracker.check_idle(idle_after, off_after).is_none(), "OFF never times out");
+    }
+
+    #[test]
+    fn activity_puts_off_the_fall_back_to_idle() {
+        let (mut tracker, clock) = tracker();
+        let idle_after = Some(Duration::from_secs(300));
+        tracker.switch_topic(2).unwrap();
+
+        // Working on the same topic well past the threshold, reporting activity along the way
+        for _ in 0..4 {
+            clock.advance(Duration::from_secs(250));
+            assert!(tracker.check_idle(idle_after, None).is_none());
+            tracker.record_activity();
+        }
+        assert_eq!(tracker.current_topic_id(), 2);
+        assert_eq!(duration(&mut tracker, 2), 1000, "no tracked time lost");
+
+        clock.advance(Duration::from_secs(300));
+        tracker.check_idle(idle_after, None).unwrap();
+        assert_eq!(tracker.current_topic_id(), 1);
+    }
+
+    #[test]
+    fn update_topic_rejects_builtin_topics_cycles_and_duplicates() {
+        let (mut tracker, _clock) = tracker();
+
+        assert!(conflict(tracker.update_topic(0, "Off", 0, 0)));
+        assert!(conflict(tracker.update_topic(1, "Away", 0, 0)));
+        assert!(conflict(tracker.update_topic(2, "Work", 2, 0)), "under itself");
+        assert!(conflict(tracker.update_topic(2, "Work", 4, 0)), "under a descendant");
+        assert!(conflict(tracker.update_topic(5, "work", 0, 0)), "same name as a sibling");
+        assert_eq!(tracker.update_topic(5, "Home", 42, 0).err(), Some(ProtocolError::ParentNotFound { parent_id: 42 }));
+        assert_eq!(tracker.update_topic(42, "Home", 0, 0).err(), Some(ProtocolError::TopicNotFound { id: 42 }));
+        assert_eq!(tracker.create_topic("Home", 42).err().and_then(|e| e.field()), Some("parent_id"));
+
+        tracker.update_topic(5, "Work", 3, 120).unwrap();
+        let topic = tracker.find_topic(5).unwrap();
+        assert_eq!((topic.name.as_str(), topic.parent_id, topic.duration), ("Work", 3, 120));
+    }
+}
@@ -0,0 +1,150 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tracing::{error, info, info_span, warn, Instrument};
+
+use timeracker_common::{ClientRequest, ProtocolError, ResponseToClient, StateChangeEvent};
+use crate::{handle_line, handle_request, ConnectionContext, NEXT_CONNECTION_ID};
+
+// WebSocket front-end. Text messages carry the same requests as the line protocol,
+// and are answered with the same JSON ResponseToClient messages, except TERMINATE which is refused:
+// a web page must not be able to stop the core. Browsers are only let in from the configured origins.
+// Once authenticated, the client receives a State snapshot, then an Event message for every change.
+// Events are subscribed to before the snapshot is taken, so the first ones may repeat changes the
+// snapshot already includes; they carry topic ids and resulting values, and are meant to be applied
+// as upserts.
+
+pub async fn accept_websocket_connections(listener: TcpListener, context: Arc<ConnectionContext>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer_addr)) => {
+                let conn_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+                let span = info_span!("ws", id = conn_id);
+                tokio::spawn(serve_websocket_connection(socket, peer_addr, context.clone()).instrument(span));
+            }
+            Err(e) => error!(error = ?e, "Error accepting WebSocket socket"),
+        }
+    }
+}
+
+async fn serve_websocket_connection(socket: TcpStream, peer_addr: SocketAddr, context: Arc<ConnectionContext>) {
+    // Clients other than browsers do not send an Origin. The error type is imposed by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let check_origin = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        let origin = request.headers().get("Origin").map(|origin| origin.to_str().unwrap_or_default());
+        match origin {
+            Some(origin) if !context.ws_allowed_origins.iter().any(|allowed| allowed == origin) => {
+                warn!(%origin, "Rejected WebSocket connection from a disallowed origin");
+                let mut error = ErrorResponse::new(Some("Origin not allowed".to_string()));
+                *error.status_mut() = StatusCode::FORBIDDEN;
+                Err(error)
+            }
+            _ => Ok(response),
+        }
+    };
+
+    let websocket = match tokio_tungstenite::accept_hdr_async(socket, check_origin).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            warn!(%peer_addr, error = %e, "WebSocket handshake failed");
+            return;
+        }
+    };
+    info!(%peer_addr, "Accepted WebSocket connection");
+    context.metrics.client_connected();
+
+    let (mut sink, mut stream) = websocket.split();
+
+    // Subscribed before taking the snapshot, so that no change falls in between
+    let mut events = context.state.subscribe();
+    let mut authenticated = context.auth_token.is_none();
+
+    if !authenticated || send_snapshot(&mut sink, &context, &mut authenticated).await.is_ok() {
+        forward_messages(&mut sink, &mut stream, &mut events, &context, &mut authenticated).await;
+    }
+
+    let _ = sink.close().await;
+    context.metrics.client_disconnected();
+    info!("WebSocket connection closed");
+}
+
+async fn forward_messages<S, R>(sink: &mut S,
+                                stream: &mut R,
+                                events: &mut broadcast::Receiver<StateChangeEvent>,
+                                context: &ConnectionContext,
+                                authenticated: &mut bool)
+    where S: SinkExt<Message> + Unpin,
+          R: StreamExt<Item = Result<Message, WsError>> + Unpin {
+    loop {
+        tokio::select! {
+            message = stream.next() => {
+                let line = match message {
+                    Some(Ok(Message::Text(line))) => line,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    // Pings are answered by tungstenite itself
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        error!(error = %e, "Error on reading from WebSocket, dropping connection");
+                        break;
+                    }
+                };
+
+                let was_authenticated = *authenticated;
+                let response = match ClientRequest::parse(line.trim()) {
+                    Ok(ClientRequest::Terminate {}) => {
+                        warn!("Refused TERMINATE over WebSocket");
+                        ProtocolError::NotAllowed { command: "TERMINATE" }.into()
+                    }
+                    _ => handle_line(line.trim(), context, authenticated).await,
+                };
+                if send(sink, &response).await.is_err() {
+                    break;
+                }
+
+                if let ResponseToClient::Bye {} = response {
+                    break;
+                }
+
+                if *authenticated && !was_authenticated
+                    && send_snapshot(sink, context, authenticated).await.is_err() {
+                    break;
+                }
+            }
+
+            event = events.recv(), if *authenticated => {
+                let sent = match event {
+                    Ok(event) => send(sink, &ResponseToClient::Event { event }).await,
+                    // Too slow to keep up, start over from a fresh snapshot
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!(missed, "WebSocket client lagging behind, sending a new snapshot");
+                        send_snapshot(sink, context, authenticated).await
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if sent.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn send_snapshot<S>(sink: &mut S, context: &ConnectionContext, authenticated: &mut bool) -> Result<(), ()>
+    where S: SinkExt<Message> + Unpin {
+    let snapshot = handle_request(ClientRequest::GetState {}, context, authenticated).await;
+    send(sink, &snapshot).await
+}
+
+async fn send<S>(sink: &mut S, response: &ResponseToClient) -> Result<(), ()>
+    where S: SinkExt<Message> + Unpin {
+    let response_str = serde_json::to_string(response).unwrap();
+    sink.send(Message::Text(response_str)).await.map_err(|_| {
+        error!("Error on sending to WebSocket, dropping connection");
+    })
+}
@@ -0,0 +1,26 @@
+use std::env;
+use std::path::PathBuf;
+
+// Generates the C header into OUT_DIR on every build, which checks that it still generates.
+// The checked-in include/timeracker.h is only rewritten on request, after changing the API:
+//   TIMERACKER_UPDATE_HEADER=1 cargo build
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).expect("Invalid cbindgen.toml");
+
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Unable to generate the C header");
+    bindings.write_to_file(out_dir.join("timeracker.h"));
+
+    if env::var_os("TIMERACKER_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(crate_dir.join("include").join("timeracker.h"));
+    }
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=TIMERACKER_UPDATE_HEADER");
+}
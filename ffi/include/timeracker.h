@@ -0,0 +1,132 @@
+/*
+ * C API of timeracker, a blocking client for timeracker_core.
+ *
+ * Ownership rules:
+ *  - every pointer returned by the library is freed by the matching timeracker_*_free function,
+ *    or timeracker_disconnect() for the client, never by the caller's own free();
+ *  - strings passed in by the caller are only borrowed for the duration of the call;
+ *  - timeracker_last_error() points to memory owned by the library.
+ *
+ * A client must not be used from several threads at once.
+ */
+
+#ifndef TIMERACKER_H
+#define TIMERACKER_H
+
+/* Generated by cbindgen from ffi/src/lib.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of every call that talks to the core.
+typedef enum TimerackerStatus {
+  TIMERACKER_STATUS_OK = 0,
+  // A NULL pointer, a string that is not valid UTF-8, or a topic name or token
+  // that is not a single word was passed in.
+  TIMERACKER_STATUS_INVALID_ARGUMENT = 1,
+  // The core could not be reached, or the connection dropped.
+  TIMERACKER_STATUS_CONNECTION_ERROR = 2,
+  // The core refused the request, see timeracker_last_error_code().
+  TIMERACKER_STATUS_SERVER_ERROR = 3,
+  // The core answered something the library does not understand.
+  TIMERACKER_STATUS_PROTOCOL_ERROR = 4,
+  // Bug in the library, the client should not be used anymore.
+  TIMERACKER_STATUS_INTERNAL_ERROR = 5,
+} TimerackerStatus;
+
+// Opaque handle on a connection to the core.
+typedef struct TimerackerClient TimerackerClient;
+
+// A topic as known by the core. `name` is owned by the library:
+// release it with timeracker_topic_free() (not needed for topics inside a TimerackerTopicList).
+typedef struct TimerackerTopic {
+  uint64_t id;
+  // 0 for top-level topics
+  uint64_t parent_id;
+  // Seconds spent on the topic
+  uint64_t duration;
+  // Unix time the topic was switched to, 0 unless it is the current topic
+  uint64_t since;
+  char *name;
+} TimerackerTopic;
+
+// Topics owned by the library, release them with timeracker_topic_list_free().
+typedef struct TimerackerTopicList {
+  struct TimerackerTopic *topics;
+  uintptr_t len;
+} TimerackerTopicList;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Connects to the core at `server` ("host:port", or the default address if NULL),
+// authenticating with `token` unless it is NULL.
+// Returns NULL on failure, see timeracker_last_error().
+//
+// # Safety
+// `server` and `token` must be NULL or valid NUL-terminated strings.
+struct TimerackerClient *timeracker_connect(const char *server, const char *token);
+
+// Says goodbye to the core and frees the client. Does nothing if `client` is NULL.
+//
+// # Safety
+// `client` must be NULL or returned by timeracker_connect(), and not used afterwards.
+void timeracker_disconnect(struct TimerackerClient *client);
+
+// Fills `out` with the topic currently tracked (OFF, id 0, when tracking is disabled).
+//
+// # Safety
+// `client` must come from timeracker_connect(), `out` must point to writable memory.
+enum TimerackerStatus timeracker_current_topic(struct TimerackerClient *client,
+                                               struct TimerackerTopic *out);
+
+// Fills `out` with every topic, including the built-in OFF (0) and Idle (1).
+//
+// # Safety
+// `client` must come from timeracker_connect(), `out` must point to writable memory.
+enum TimerackerStatus timeracker_list_topics(struct TimerackerClient *client,
+                                             struct TimerackerTopicList *out);
+
+// Starts tracking time on topic `id` (0 disables tracking).
+//
+// # Safety
+// `client` must come from timeracker_connect().
+enum TimerackerStatus timeracker_switch_topic(struct TimerackerClient *client, uint64_t id);
+
+// Creates a topic named `name` under `parent_id` (0 for a top-level topic).
+// The name must be a single word, without spaces or line breaks.
+//
+// # Safety
+// `client` must come from timeracker_connect(), `name` must be a valid NUL-terminated string.
+enum TimerackerStatus timeracker_create_topic(struct TimerackerClient *client,
+                                              const char *name,
+                                              uint64_t parent_id);
+
+// Frees the name of a topic filled by timeracker_current_topic(), and sets it to NULL.
+//
+// # Safety
+// `topic` must be NULL or filled by timeracker_current_topic().
+void timeracker_topic_free(struct TimerackerTopic *topic);
+
+// Frees the topics of a list filled by timeracker_list_topics(), and empties it.
+//
+// # Safety
+// `list` must be NULL or filled by timeracker_list_topics().
+void timeracker_topic_list_free(struct TimerackerTopicList *list);
+
+// Message of the last error on the calling thread, empty if there was none.
+// Owned by the library and valid until the next call on the same thread.
+const char *timeracker_last_error(void);
+
+// Error code sent by the core with the last TIMERACKER_STATUS_SERVER_ERROR on the calling thread,
+// 0 otherwise. See the error catalog of timeracker_core.
+uint64_t timeracker_last_error_code(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* TIMERACKER_H */
@@ -0,0 +1,271 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use timeracker_common::{BlockingTimeRackerClient, ClientError, ClientOptions, ClientRequest, TimeTrackingTopic};
+
+// C API over the blocking client. The ownership rules are spelled out at the top of
+// include/timeracker.h, generated from this file and cbindgen.toml, see build.rs.
+
+/// Result of every call that talks to the core.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimerackerStatus {
+    Ok = 0,
+    /// A NULL pointer, a string that is not valid UTF-8, or a topic name or token
+    /// that is not a single word was passed in.
+    InvalidArgument = 1,
+    /// The core could not be reached, or the connection dropped.
+    ConnectionError = 2,
+    /// The core refused the request, see timeracker_last_error_code().
+    ServerError = 3,
+    /// The core answered something the library does not understand.
+    ProtocolError = 4,
+    /// Bug in the library, the client should not be used anymore.
+    InternalError = 5,
+}
+
+/// Opaque handle on a connection to the core.
+pub struct TimerackerClient {
+    client: BlockingTimeRackerClient,
+}
+
+/// A topic as known by the core. `name` is owned by the library:
+/// release it with timeracker_topic_free() (not needed for topics inside a TimerackerTopicList).
+#[repr(C)]
+pub struct TimerackerTopic {
+    pub id: u64,
+    /// 0 for top-level topics
+    pub parent_id: u64,
+    /// Seconds spent on the topic
+    pub duration: u64,
+    /// Unix time the topic was switched to, 0 unless it is the current topic
+    pub since: u64,
+    pub name: *mut c_char,
+}
+
+/// Topics owned by the library, release them with timeracker_topic_list_free().
+#[repr(C)]
+pub struct TimerackerTopicList {
+    pub topics: *mut TimerackerTopic,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<(u64, CString)> = RefCell::new((0, CString::default()));
+}
+
+/// Connects to the core at `server` ("host:port", or the default address if NULL),
+/// authenticating with `token` unless it is NULL.
+/// Returns NULL on failure, see timeracker_last_error().
+///
+/// # Safety
+/// `server` and `token` must be NULL or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn timeracker_connect(server: *const c_char, token: *const c_char) -> *mut TimerackerClient {
+    let mut client = ptr::null_mut();
+    guard(|| {
+        let mut options = ClientOptions::new();
+        if let Some(server) = optional_str(server)? {
+            options.server = server.to_string();
+        }
+        options.token = optional_str(token)?.map(str::to_string);
+
+        let connected = BlockingTimeRackerClient::connect(options).map_err(status_from_error)?;
+        client = Box::into_raw(Box::new(TimerackerClient { client: connected }));
+        Ok(())
+    });
+    client
+}
+
+/// Says goodbye to the core and frees the client. Does nothing if `client` is NULL.
+///
+/// # Safety
+/// `client` must be NULL or returned by timeracker_connect(), and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn timeracker_disconnect(client: *mut TimerackerClient) {
+    if client.is_null() {
+        return;
+    }
+    let mut client = Box::from_raw(client);
+    guard(|| client.client.bye().map_err(status_from_error));
+}
+
+/// Fills `out` with the topic currently tracked (OFF, id 0, when tracking is disabled).
+///
+/// # Safety
+/// `client` must come from timeracker_connect(), `out` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn timeracker_current_topic(client: *mut TimerackerClient, out: *mut TimerackerTopic) -> TimerackerStatus {
+    guard(|| {
+        let client = client.as_mut().ok_or_else(|| invalid_argument("client is NULL"))?;
+        if out.is_null() {
+            return Err(invalid_argument("out is NULL"));
+        }
+
+        let state = client.client.get_state().map_err(status_from_error)?;
+        let topic = state.topics_tree.iter()
+            .find(|topic| topic.id == state.current_topic_id)
+            .ok_or_else(|| set_error(TimerackerStatus::ProtocolError, 0, "Current topic missing from the state"))?;
+
+        out.write(to_c_topic(topic, state.current_topic_since));
+        Ok(())
+    })
+}
+
+/// Fills `out` with every topic, including the built-in OFF (0) and Idle (1).
+///
+/// # Safety
+/// `client` must come from timeracker_connect(), `out` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn timeracker_list_topics(client: *mut TimerackerClient, out: *mut TimerackerTopicList) -> TimerackerStatus {
+    guard(|| {
+        let client = client.as_mut().ok_or_else(|| invalid_argument("client is NULL"))?;
+        if out.is_null() {
+            return Err(invalid_argument("out is NULL"));
+        }
+
+        let state = client.client.get_state().map_err(status_from_error)?;
+        let topics: Box<[TimerackerTopic]> = state.topics_tree.iter()
+            .map(|topic| {
+                let since = if topic.id == state.current_topic_id { state.current_topic_since } else { 0 };
+                to_c_topic(topic, since)
+            })
+            .collect();
+
+        let len = topics.len();
+        out.write(TimerackerTopicList { topics: Box::into_raw(topics) as *mut TimerackerTopic, len });
+        Ok(())
+    })
+}
+
+/// Starts tracking time on topic `id` (0 disables tracking).
+///
+/// # Safety
+/// `client` must come from timeracker_connect().
+#[no_mangle]
+pub unsafe extern "C" fn timeracker_switch_topic(client: *mut TimerackerClient, id: u64) -> TimerackerStatus {
+    guard(|| {
+        let client = client.as_mut().ok_or_else(|| invalid_argument("client is NULL"))?;
+        client.client.switch_topic(id).map(|_| ()).map_err(status_from_error)
+    })
+}
+
+/// Creates a topic named `name` under `parent_id` (0 for a top-level topic).
+/// The name must be a single word, without spaces or line breaks.
+///
+/// # Safety
+/// `client` must come from timeracker_connect(), `name` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn timeracker_create_topic(client: *mut TimerackerClient, name: *const c_char, parent_id: u64) -> TimerackerStatus {
+    guard(|| {
+        let client = client.as_mut().ok_or_else(|| invalid_argument("client is NULL"))?;
+        let name = optional_str(name)?.ok_or_else(|| invalid_argument("name is NULL"))?;
+        ClientRequest::CreateTopic { name: name.to_string(), parent_id }.validate()
+            .map_err(|e| invalid_argument(&e.to_string()))?;
+        client.client.create_topic(name, parent_id).map(|_| ()).map_err(status_from_error)
+    })
+}
+
+/// Frees the name of a topic filled by timeracker_current_topic(), and sets it to NULL.
+///
+/// # Safety
+/// `topic` must be NULL or filled by timeracker_current_topic().
+#[no_mangle]
+pub unsafe extern "C" fn timeracker_topic_free(topic: *mut TimerackerTopic) {
+    if let Some(topic) = topic.as_mut() {
+        free_topic_name(topic);
+    }
+}
+
+/// Frees the topics of a list filled by timeracker_list_topics(), and empties it.
+///
+/// # Safety
+/// `list` must be NULL or filled by timeracker_list_topics().
+#[no_mangle]
+pub unsafe extern "C" fn timeracker_topic_list_free(list: *mut TimerackerTopicList) {
+    let list = match list.as_mut() {
+        Some(list) if !list.topics.is_null() => list,
+        _ => return,
+    };
+
+    let mut topics = Box::from_raw(ptr::slice_from_raw_parts_mut(list.topics, list.len));
+    for topic in topics.iter_mut() {
+        free_topic_name(topic);
+    }
+    list.topics = ptr::null_mut();
+    list.len = 0;
+}
+
+/// Message of the last error on the calling thread, empty if there was none.
+/// Owned by the library and valid until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn timeracker_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().1.as_ptr())
+}
+
+/// Error code sent by the core with the last TIMERACKER_STATUS_SERVER_ERROR on the calling thread,
+/// 0 otherwise. See the error catalog of timeracker_core.
+#[no_mangle]
+pub extern "C" fn timeracker_last_error_code() -> u64 {
+    LAST_ERROR.with(|last_error| last_error.borrow().0)
+}
+
+// Clears the last error, runs `f`, and never lets a panic unwind into C
+fn guard<F: FnOnce() -> Result<(), TimerackerStatus>>(f: F) -> TimerackerStatus {
+    set_error(TimerackerStatus::Ok, 0, "");
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => TimerackerStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => set_error(TimerackerStatus::InternalError, 0, "Panic in timeracker"),
+    }
+}
+
+fn set_error(status: TimerackerStatus, error_code: u64, msg: &str) -> TimerackerStatus {
+    let msg = CString::new(msg.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = (error_code, msg));
+    status
+}
+
+fn invalid_argument(msg: &str) -> TimerackerStatus {
+    set_error(TimerackerStatus::InvalidArgument, 0, msg)
+}
+
+fn status_from_error(error: ClientError) -> TimerackerStatus {
+    let (status, error_code) = match &error {
+        ClientError::Io(_) | ClientError::Timeout | ClientError::ConnectionClosed => (TimerackerStatus::ConnectionError, 0),
+        ClientError::Decode(_) | ClientError::UnexpectedResponse { .. } => (TimerackerStatus::ProtocolError, 0),
+        ClientError::InvalidRequest(_) => (TimerackerStatus::InvalidArgument, 0),
+        ClientError::Server(e) => (TimerackerStatus::ServerError, e.error_code),
+    };
+    set_error(status, error_code, &error.to_string())
+}
+
+unsafe fn optional_str<'a>(s: *const c_char) -> Result<Option<&'a str>, TimerackerStatus> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(s).to_str()
+        .map(Some)
+        .map_err(|_| invalid_argument("string argument is not valid UTF-8"))
+}
+
+fn to_c_topic(topic: &TimeTrackingTopic, since: u64) -> TimerackerTopic {
+    let name = CString::new(topic.name.replace('\0', "")).unwrap_or_default();
+    TimerackerTopic {
+        id: topic.id,
+        parent_id: topic.parent_id,
+        duration: topic.duration,
+        since,
+        name: name.into_raw(),
+    }
+}
+
+unsafe fn free_topic_name(topic: &mut TimerackerTopic) {
+    if !topic.name.is_null() {
+        drop(CString::from_raw(topic.name));
+        topic.name = ptr::null_mut();
+    }
+}